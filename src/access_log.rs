@@ -0,0 +1,121 @@
+use std::{
+	io::{self, BufWriter, Write as _},
+	net::IpAddr,
+	time::Instant
+};
+
+use actix_web::{
+	body::MessageBody,
+	dev::{ServiceRequest, ServiceResponse},
+	http::header::{ETAG, USER_AGENT},
+	middleware::Next,
+	web
+};
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+
+use crate::{api::v1::CacheResult, client_ip::TrustedProxies};
+
+/// Where the access log is written. Lines are handed to a dedicated thread
+/// which writes them to stdout through a buffer, so requests never wait for
+/// stdout. If that thread falls too far behind, lines are dropped instead.
+pub struct AccessLog(NonBlocking);
+
+impl AccessLog {
+	/// Starts the thread writing to stdout. The lines which haven't been
+	/// written yet are flushed once the returned guard is dropped.
+	pub fn stdout() -> (Self, WorkerGuard) {
+		let (writer, guard) =
+			tracing_appender::non_blocking(BufWriter::new(io::stdout()));
+		(Self(writer), guard)
+	}
+
+	fn write(&self, line: &str) {
+		// Writing only fails once the thread has stopped, which happens on shutdown
+		let _ = self.0.clone().write_all(format!("{line}\n").as_bytes());
+	}
+}
+
+/// A single line of the access log
+#[derive(Serialize)]
+struct AccessLogEntry<'a> {
+	timestamp: String,
+	method: &'a str,
+	path: &'a str,
+	query: &'a str,
+	status: u16,
+	latency_ms: f64,
+	/// Only set for responses that went through the response cache
+	cache: Option<CacheResult>,
+	etag: Option<&'a str>,
+	user_agent: Option<&'a str>,
+	client_ip: Option<IpAddr>
+}
+
+/// Writes a JSON line to the [`AccessLog`] for every request once its response
+/// is ready
+pub async fn access_log_middleware(
+	service_request: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	let Some(access_log) = service_request.app_data::<web::Data<AccessLog>>().cloned()
+	else {
+		return next.call(service_request).await;
+	};
+	let start = Instant::now();
+	let timestamp = OffsetDateTime::now_utc()
+		.format(&Rfc3339)
+		.unwrap_or_default();
+
+	// The request can't be borrowed across the call, as routing needs it to be
+	// uniquely owned
+	let method = service_request.method().clone();
+	let path = service_request.path().to_string();
+	let query = service_request.query_string().to_string();
+	let user_agent = service_request
+		.headers()
+		.get(USER_AGENT)
+		.and_then(|v| v.to_str().ok())
+		.map(str::to_string);
+	let client_ip = service_request
+		.app_data::<web::Data<TrustedProxies>>()
+		.and_then(|proxies| proxies.client_ip(service_request.request()));
+
+	let response = next.call(service_request).await;
+
+	let (status, cache, etag) = match &response {
+		Ok(response) => (
+			response.status(),
+			response
+				.response()
+				.extensions()
+				.get::<CacheResult>()
+				.copied(),
+			response
+				.headers()
+				.get(ETAG)
+				.and_then(|v| v.to_str().ok())
+				.map(str::to_string)
+		),
+		Err(e) => (e.as_response_error().status_code(), None, None)
+	};
+
+	let entry = AccessLogEntry {
+		timestamp,
+		method: method.as_str(),
+		path: &path,
+		query: &query,
+		status: status.as_u16(),
+		latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+		cache,
+		etag: etag.as_deref(),
+		user_agent: user_agent.as_deref(),
+		client_ip
+	};
+	if let Ok(line) = serde_json::to_string(&entry) {
+		access_log.write(&line);
+	}
+
+	response
+}
@@ -1,190 +1,913 @@
-use std::fmt::Display;
+use std::sync::LazyLock;
 
 use actix_web::{
+	body::{EitherBody, MessageBody},
+	dev::{Path, ResourceDef, ServiceRequest, ServiceResponse, Url},
 	get,
+	http::StatusCode,
+	middleware::Next,
+	post,
 	web::{self, ServiceConfig},
 	HttpResponse,
 	Responder
 };
+use futures_util::future;
+use metrics::histogram;
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinSet;
+use tracing::Instrument as _;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
 	api::v1::{
-		responses::{consts::*, ArtifactResponse, Checksum, ChecksumType, ErrorResponse},
-		ApiData
+		changelog,
+		middleware::{etag_middleware, update_check_middleware},
+		responses::{
+			consts::*,
+			ArtifactChange,
+			ArtifactDiffResponse,
+			ArtifactResponse,
+			BatchResponse,
+			BatchResult,
+			ChangeAction,
+			Checksum,
+			ChecksumType,
+			ErrorResponse,
+			RevokedArtifact
+		},
+		ApiData,
+		CurrentApiData
 	},
-	maven::{self, MavenError},
-	types::gradle_module_metadata::{
-		ArtifactSelector,
-		Dependency,
-		ThirdPartyCompatibility,
-		Variant,
-		VersionRequirement
+	compatibility,
+	maven::{self, MavenError, UpstreamLookups},
+	metrics::RESOLVED_DEPENDENCIES,
+	rollout::RolloutCohort,
+	statistics::UpdateCheck,
+	types::{
+		gradle_module_metadata::{
+			ArtifactSelector,
+			Dependency,
+			ThirdPartyCompatibility,
+			Variant,
+			VersionRequirement
+		},
+		oneconfig::{Channel, ModLoader, ONECONFIG_GROUP}
 	}
 };
 
-const ONECONFIG_GROUP: &str = "org.polyfrost.oneconfig";
+/// The most queries a batch may hold, as they are all resolved at once
+const MAX_BATCH_QUERIES: usize = 32;
+
+/// The route of [`platform_agnostic_artifacts`], which matches any artifact
+const ARTIFACT_ROUTE: &str = "/v1/artifacts/{artifact}";
+static ARTIFACT_RESOURCE: LazyLock<ResourceDef> =
+	LazyLock::new(|| ResourceDef::new(ARTIFACT_ROUTE));
 
 pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
 	|config| {
 		config.service(
 			web::scope("/artifacts")
+				.wrap(actix_web::middleware::from_fn(etag_middleware))
+				// Wraps the cache, so that cached responses are counted as well
+				.wrap(actix_web::middleware::from_fn(update_check_middleware))
+				// Wraps the cache, so that unknown artifacts are never cached
+				.wrap(actix_web::middleware::from_fn(known_artifact_middleware))
 				.service(oneconfig)
+				.service(oneconfig_updates)
+				.service(batch)
+				.service(changelog::changelog)
 				.service(platform_agnostic_artifacts)
 		);
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
-#[serde(rename_all = "lowercase")]
-pub enum ModLoader {
-	Forge,
-	Fabric
-}
+/// Responds to requests for artifacts which aren't served before they reach the
+/// response cache, as [`platform_agnostic_artifacts`] matches any name and the
+/// cache would otherwise keep a response for each of them
+async fn known_artifact_middleware(
+	service_request: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+	// The other routes of the scope are matched first, so only requests which are
+	// routed to the catch-all one are checked
+	let mut path = Path::new(Url::new(service_request.uri().clone()));
+	let unknown = match CurrentApiData::get(service_request.request()) {
+		Some(state)
+			if service_request.match_pattern().as_deref() == Some(ARTIFACT_ROUTE)
+				&& ARTIFACT_RESOURCE.capture_match_info(&mut path) =>
+			path.get("artifact")
+				.filter(|artifact| {
+					!state.artifacts.iter().any(|served| served == artifact)
+				})
+				.map(str::to_string),
+		_ => None
+	};
+	let Some(artifact) = unknown else {
+		return next
+			.call(service_request)
+			.await
+			.map(|res| res.map_into_left_body());
+	};
 
-impl Display for ModLoader {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		f.write_str(match self {
-			Self::Fabric => "fabric",
-			Self::Forge => "forge"
-		})
-	}
+	// The query only names the repository in the problem, so an invalid one is
+	// still answered as an unknown artifact
+	let repository =
+		web::Query::<ArtifactQuery>::from_query(service_request.query_string())
+			.map_or("releases", |query| query.repository());
+	let response = unknown_artifact(&artifact, repository);
+	Ok(service_request
+		.into_response(HttpResponse::from(response))
+		.map_into_right_body())
 }
 
-#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct OneConfigVersionInfo {
 	/// The minecraft version to fetch artifacts for
+	#[param(example = "1.8.9")]
 	version: String,
 	/// The mod loader to fetch artifacts for
 	loader: ModLoader
 }
 
-#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
+#[derive(
+	Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, IntoParams, ToSchema,
+)]
+#[into_params(parameter_in = Query)]
 pub struct ArtifactQuery<V = ()> {
 	/// Whether or not to use snapshots instead of official releases
 	#[serde(default)]
 	snapshots: bool,
-	/// Extra version information
+	/// If set, only resolves versions which are at least as stable as this
+	/// channel. The snapshot channel implies `snapshots`.
+	channel: Option<Channel>,
+	/// If set, resolves this exact artifact version rather than the latest one
+	#[serde(default)]
+	#[param(value_type = Option<String>, example = "1.0.0-alpha.50")]
+	#[schema(value_type = Option<String>, example = "1.0.0-alpha.50")]
+	pin: Option<Version>,
+	/// If set, the version of the artifact the client has installed, which
+	/// marks the resolved artifact as required if it is below the minimum
+	/// version
+	#[param(example = "1.0.0-alpha.40")]
+	installed: Option<String>,
+	/// Whether to open the resolved jars and include the metadata of the mods
+	/// in them, such as their IDs and names
+	#[serde(default)]
+	metadata: bool,
+	/// Extra version information, documented by its own parameters
 	#[serde(flatten)]
+	#[param(ignore)]
+	#[schema(ignore)]
 	version_info: V
 }
 
+/// An artifact installed by the client, identified by its version, its
+/// checksum or both
+#[derive(Deserialize, ToSchema)]
+pub struct InstalledArtifact {
+	#[schema(example = "org.polyfrost.oneconfig")]
+	group: String,
+	#[schema(example = "1.8.9-forge")]
+	name: String,
+	/// The installed version, which must be sent unless the checksum is
+	#[schema(example = "1.0.0-alpha.50")]
+	version: Option<String>,
+	/// The SHA-256 checksum of the installed file, which must be sent unless
+	/// the version is
+	checksum: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateCheckRequest {
+	/// Every artifact installed from a previous update check, including
+	/// OneConfig itself
+	installed: Vec<InstalledArtifact>
+}
+
+/// A query of a batch, which takes the same parameters as resolving its
+/// artifact on its own
+#[derive(Deserialize, ToSchema)]
+pub struct BatchQuery {
+	/// Either `oneconfig`, or an artifact shared by every Minecraft version and
+	/// mod loader such as stage1
+	#[schema(example = "oneconfig")]
+	artifact: String,
+	/// The Minecraft version to resolve OneConfig for, which is only sent for
+	/// `oneconfig`
+	#[schema(example = "1.8.9")]
+	version: Option<String>,
+	/// The mod loader to resolve OneConfig for, which is only sent for
+	/// `oneconfig`
+	loader: Option<ModLoader>,
+	#[serde(flatten)]
+	query: ArtifactQuery
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchRequest {
+	/// The queries to resolve, of which there may be at most 32
+	queries: Vec<BatchQuery>
+}
+
+impl<V> ArtifactQuery<V> {
+	/// The maven repository that artifacts are resolved from
+	fn repository(&self) -> &'static str {
+		if self.snapshots || self.channel == Some(Channel::Snapshot) {
+			"snapshots"
+		} else {
+			"releases"
+		}
+	}
+
+	/// The same query with other version information
+	fn with_version_info<W>(self, version_info: W) -> ArtifactQuery<W> {
+		ArtifactQuery {
+			snapshots: self.snapshots,
+			channel: self.channel,
+			pin: self.pin,
+			installed: self.installed,
+			metadata: self.metadata,
+			version_info
+		}
+	}
+}
+
+/// Resolves the latest OneConfig version for a Minecraft version and mod
+/// loader, along with every artifact bundled with it
+#[utoipa::path(
+	context_path = "/v1/artifacts",
+	tag = "artifacts",
+	params(
+		ArtifactQuery,
+		OneConfigVersionInfo,
+		("X-Client-Id" = Option<String>, Header, description = "A stable identifier of the client, which decides whether it receives versions that are being rolled out")
+	),
+	responses(
+		(status = OK, description = "OneConfig and its bundled artifacts", body = [ArtifactResponse]),
+		(
+			status = "4XX",
+			description = "The requested OneConfig version does not exist, or the query is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		),
+		(
+			status = "5XX",
+			description = "The artifacts could not be resolved from maven",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
 #[get("/oneconfig")]
-async fn oneconfig(
-	state: web::Data<ApiData>,
+pub async fn oneconfig(
+	state: CurrentApiData,
+	cohort: RolloutCohort,
 	query: web::Query<ArtifactQuery<OneConfigVersionInfo>>
 ) -> impl Responder {
-	let mut artifacts = Vec::<ArtifactResponse>::new();
-	let repository = if query.snapshots {
-		"snapshots"
-	} else {
-		"releases"
+	let resolved = match resolve_oneconfig(&state, None, &cohort, &query).await {
+		Ok(resolved) => resolved,
+		Err(e) => return e.into()
 	};
-	let oneconfig_variant = format!(
-		"{}-{}",
-		query.version_info.version, query.version_info.loader
-	);
+	let artifacts = resolved
+		.artifacts
+		.into_iter()
+		.map(|artifact| artifact.response)
+		.collect::<Vec<_>>();
 
-	let latest_oneconfig_version = match maven::fetch_latest_artifact(
-		&state,
-		repository,
-		ONECONFIG_GROUP,
-		&oneconfig_variant
+	// Convert artifacts to JSON and insert a copy into the cache
+	let response = match serde_json::to_string(&artifacts) {
+		Ok(response) => response,
+		Err(e) =>
+			return ErrorResponse::internal_error(
+				format!("Error constructing oneconfig artifacts: {e}"),
+				&resolved.instance_query
+			)
+			.into(),
+	};
+
+	let mut res = HttpResponse::Ok()
+		.content_type("application/json")
+		.body(response);
+	res.extensions_mut().insert(resolved.update_check);
+	res
+}
+
+/// Compares the artifacts a client has installed with the latest OneConfig
+/// version for a Minecraft version and mod loader, responding with only the
+/// artifacts to add, update or remove
+#[utoipa::path(
+	context_path = "/v1/artifacts",
+	tag = "artifacts",
+	params(
+		ArtifactQuery,
+		OneConfigVersionInfo,
+		("X-Client-Id" = Option<String>, Header, description = "A stable identifier of the client, which decides whether it receives versions that are being rolled out")
+	),
+	request_body = UpdateCheckRequest,
+	responses(
+		(status = OK, description = "The changes to make to the installed artifacts", body = ArtifactDiffResponse),
+		(
+			status = "4XX",
+			description = "The requested OneConfig version does not exist, or the query or body is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		),
+		(
+			status = "5XX",
+			description = "The artifacts could not be resolved from maven",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
 	)
-	.await
+)]
+#[post("/oneconfig/updates")]
+pub async fn oneconfig_updates(
+	state: CurrentApiData,
+	cohort: RolloutCohort,
+	query: web::Query<ArtifactQuery<OneConfigVersionInfo>>,
+	body: web::Json<UpdateCheckRequest>
+) -> impl Responder {
+	if let Some(installed) = body
+		.installed
+		.iter()
+		.find(|installed| installed.version.is_none() && installed.checksum.is_none())
 	{
-		Ok(v) => v,
-		Err(MavenError::Reqwest(e)) if e.status().is_some_and(|c| c == 404) =>
-			return ErrorResponse::InvalidOneConfigVersion {
-				title: INVALID_ONECONFIG_VERSION_TITLE.to_string(),
-				detail: format!(
-					"The requested version {oneconfig_variant} could not be found in \
-					 the requested {repository} repository"
+		return ErrorResponse::InvalidBody {
+			title: INVALID_BODY_TITLE.to_string(),
+			detail: format!(
+				"Either the version or the checksum of {group}:{name} must be sent",
+				group = installed.group,
+				name = installed.name
+			),
+			instance: format!(
+				"{INVALID_BODY_INSTANCE_PREFIX}?version={version}&loader={loader}",
+				version = query.version_info.version,
+				loader = query.version_info.loader
+			)
+		}
+		.into();
+	}
+
+	let resolved = match resolve_oneconfig(&state, None, &cohort, &query).await {
+		Ok(resolved) => resolved,
+		Err(e) => return e.into()
+	};
+	let oneconfig_version = resolved.update_check.oneconfig_version.clone();
+	let mut installed = body.into_inner().installed;
+	let mut changes = Vec::new();
+	let revoked = installed
+		.iter()
+		.filter_map(|installed| {
+			let version = installed.version.as_ref()?;
+			let reason = state.revocation(&installed.group, &installed.name, version)?;
+			Some(RevokedArtifact {
+				group: installed.group.clone(),
+				name: installed.name.clone(),
+				version: version.clone(),
+				reason: reason.to_string()
+			})
+		})
+		.collect::<Vec<_>>();
+
+	for artifact in resolved.artifacts {
+		let position = installed.iter().position(|installed| {
+			installed.group == artifact.response.group
+				&& installed.name == artifact.response.name
+		});
+		let Some(position) = position else {
+			changes.push(ArtifactChange {
+				action: ChangeAction::Add,
+				reason: format!(
+					"{group}:{name} is bundled with OneConfig {oneconfig_version}, but \
+					 is not installed",
+					group = artifact.response.group,
+					name = artifact.response.name
 				),
-				instance: format!(
-					"{INVALID_ONECONFIG_VERSION_INSTANCE_PREFIX}?version={version}&\
-					 loader={loader}&repository={repository}",
-					version = query.version_info.version,
-					loader = query.version_info.loader
+				group: artifact.response.group.clone(),
+				name: artifact.response.name.clone(),
+				installed_version: None,
+				version: Some(artifact.version),
+				artifact: Some(artifact.response),
+				required: false
+			});
+			continue;
+		};
+
+		let installed = installed.swap_remove(position);
+		if let Some(reason) = update_reason(&installed, &artifact) {
+			let required = installed.version.as_ref().is_some_and(|version| {
+				state.update_required(
+					&installed.group,
+					&installed.name,
+					version,
+					Some((&resolved.minecraft_version, &resolved.loader))
 				)
+			});
+			let revocation = revoked.iter().find(|revoked| {
+				revoked.group == installed.group && revoked.name == installed.name
+			});
+			changes.push(ArtifactChange {
+				action: ChangeAction::Update,
+				// Moving off of a revoked version is more important than anything else
+				reason: match revocation {
+					Some(revoked) => format!(
+						"The installed version {version} was revoked: {reason}",
+						version = revoked.version,
+						reason = revoked.reason
+					),
+					None => reason
+				},
+				group: installed.group,
+				name: installed.name,
+				installed_version: installed.version,
+				version: Some(artifact.version),
+				artifact: Some(artifact.response),
+				required
+			});
+		}
+	}
+
+	// Whatever is left over is not part of this OneConfig version anymore
+	changes.extend(installed.into_iter().map(|installed| ArtifactChange {
+		action: ChangeAction::Remove,
+		reason: format!(
+			"{group}:{name} is not bundled with OneConfig {oneconfig_version}",
+			group = installed.group,
+			name = installed.name
+		),
+		group: installed.group,
+		name: installed.name,
+		installed_version: installed.version,
+		version: None,
+		artifact: None,
+		required: false
+	}));
+
+	let mut res = HttpResponse::Ok().json(ArtifactDiffResponse {
+		oneconfig_version,
+		minecraft_version: resolved.minecraft_version,
+		loader: resolved.loader,
+		required: changes.iter().any(|change| change.required),
+		changes,
+		revoked
+	});
+	res.extensions_mut().insert(resolved.update_check);
+	res
+}
+
+/// Resolves several queries at once, such as OneConfig for a few Minecraft
+/// versions and mod loaders along with stage1. The queries are resolved
+/// concurrently and share the files they fetch from maven, and each of them
+/// succeeds or fails on its own.
+#[utoipa::path(
+	context_path = "/v1/artifacts",
+	tag = "artifacts",
+	params(
+		("X-Client-Id" = Option<String>, Header, description = "A stable identifier of the client, which decides whether it receives versions that are being rolled out")
+	),
+	request_body = BatchRequest,
+	responses(
+		(status = OK, description = "The result of each query, in the same order as the queries", body = BatchResponse),
+		(
+			status = "4XX",
+			description = "The body is invalid, or holds too many queries",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[post("/batch")]
+pub async fn batch(
+	state: CurrentApiData,
+	cohort: RolloutCohort,
+	body: web::Json<BatchRequest>
+) -> impl Responder {
+	let queries = body.into_inner().queries;
+	if queries.len() > MAX_BATCH_QUERIES {
+		return ErrorResponse::InvalidBody {
+			title: INVALID_BODY_TITLE.to_string(),
+			detail: format!(
+				"A batch may hold at most {MAX_BATCH_QUERIES} queries, but {count} were \
+				 sent",
+				count = queries.len()
+			),
+			instance: format!(
+				"{INVALID_BODY_INSTANCE_PREFIX}?queries={count}",
+				count = queries.len()
+			)
+		}
+		.into();
+	}
+
+	let lookups = UpstreamLookups::builder().build();
+	let resolved = future::join_all(
+		queries
+			.into_iter()
+			.map(|query| resolve_batch_query(&state, &lookups, &cohort, query))
+	)
+	.await;
+
+	let mut update_checks = Vec::new();
+	let results = resolved
+		.into_iter()
+		.map(|result| match result {
+			Ok((artifacts, update_check)) => {
+				update_checks.extend(update_check);
+				BatchResult {
+					status: StatusCode::OK.as_u16(),
+					artifacts: Some(artifacts),
+					problem: None
+				}
 			}
-			.into(),
-		// Err(_) => unreachable!() // TODO add Semver handling, and NoVersions
-		Err(e) => {
-			return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!("Error fetching latest oneconfig version: {e:?}"));
+			Err(problem) => BatchResult {
+				status: problem.status().as_u16(),
+				artifacts: None,
+				problem: Some(problem)
+			}
+		})
+		.collect();
+
+	let mut res = HttpResponse::Ok().json(BatchResponse { results });
+	res.extensions_mut().insert(update_checks);
+	res
+}
+
+/// Resolves a query of a batch the same way as its own endpoint, along with
+/// the update check it answered if it is for OneConfig
+async fn resolve_batch_query(
+	state: &CurrentApiData,
+	lookups: &UpstreamLookups,
+	cohort: &RolloutCohort,
+	query: BatchQuery
+) -> Result<(Vec<ArtifactResponse>, Option<UpdateCheck>), ErrorResponse> {
+	let BatchQuery {
+		artifact,
+		version,
+		loader,
+		query
+	} = query;
+	let invalid = |detail: String| ErrorResponse::InvalidBody {
+		title: INVALID_BODY_TITLE.to_string(),
+		detail,
+		instance: format!("{INVALID_BODY_INSTANCE_PREFIX}?artifact={artifact}")
+	};
+
+	if artifact != "oneconfig" {
+		if version.is_some() || loader.is_some() {
+			return Err(invalid(format!(
+				"{artifact} is shared by every Minecraft version and mod loader, so \
+				 neither can be sent for it"
+			)));
 		}
+
+		let response =
+			resolve_artifact(state, Some(lookups), cohort, &artifact, &query).await?;
+		return Ok((vec![response], None));
+	}
+
+	let (Some(version), Some(loader)) = (version, loader) else {
+		return Err(invalid(
+			"OneConfig is resolved for a Minecraft version and mod loader, so both must \
+			 be sent"
+				.to_string()
+		));
 	};
+	let query = query.with_version_info(OneConfigVersionInfo { version, loader });
+	let resolved = resolve_oneconfig(state, Some(lookups), cohort, &query).await?;
 
-	// Add oneconfig itself to the artifacts
-	let latest_oneconfig_url = format!(
-		"{maven_url}{repository}/{group}/{artifact}/{version}/{artifact}-{version}.jar",
-		maven_url = state.public_maven_url,
-		group = ONECONFIG_GROUP.replace('.', "/"),
-		artifact = format!(
-			"{}-{}",
-			query.version_info.version, query.version_info.loader
-		),
-		version = latest_oneconfig_version,
+	Ok((
+		resolved
+			.artifacts
+			.into_iter()
+			.map(|artifact| artifact.response)
+			.collect(),
+		Some(resolved.update_check)
+	))
+}
+
+/// Why an installed artifact needs to be replaced with the resolved one, or
+/// [`None`] if it is up to date. The checksum is preferred when both it and
+/// the version are sent, as it also catches corrupted or modified files.
+fn update_reason(
+	installed: &InstalledArtifact,
+	artifact: &ResolvedArtifact
+) -> Option<String> {
+	let expected = &artifact.response.checksum.hash;
+
+	if let Some(checksum) = &installed.checksum {
+		if checksum.trim().eq_ignore_ascii_case(expected.trim()) {
+			return None;
+		}
+
+		return Some(match &installed.version {
+			Some(version) if *version == artifact.version => format!(
+				"The installed file of version {version} does not match its checksum, \
+				 so it may be corrupted"
+			),
+			_ => format!(
+				"The installed file does not match the checksum of version {version}",
+				version = artifact.version
+			)
+		});
+	}
+
+	let installed_version = installed.version.as_ref()?;
+	if *installed_version == artifact.version {
+		return None;
+	}
+
+	let newer = match (
+		Version::parse(installed_version),
+		Version::parse(&artifact.version)
+	) {
+		(Ok(installed), Ok(resolved)) => resolved > installed,
+		_ => true
+	};
+	Some(
+		if newer {
+			format!(
+				"Version {version} is newer than the installed version \
+				 {installed_version}",
+				version = artifact.version
+			)
+		} else {
+			format!(
+				"Version {version} replaces the installed version {installed_version}",
+				version = artifact.version
+			)
+		}
+	)
+}
+
+/// An artifact resolved for a OneConfig version, along with its version which
+/// isn't part of the response
+struct ResolvedArtifact {
+	version: String,
+	response: ArtifactResponse
+}
+
+struct ResolvedOneConfig {
+	/// OneConfig itself, followed by every artifact bundled with it
+	artifacts: Vec<ResolvedArtifact>,
+	/// The Minecraft version and mod loader of the OneConfig variant, which
+	/// differ from the requested ones if they were mapped or fell back to
+	/// others
+	minecraft_version: String,
+	loader: ModLoader,
+	update_check: UpdateCheck,
+	instance_query: String
+}
+
+/// Resolves the OneConfig version matching the query, along with every
+/// artifact bundled with it. The `lookups` are shared with other queries that
+/// are resolved at the same time, if there are any.
+async fn resolve_oneconfig(
+	state: &CurrentApiData,
+	lookups: Option<&UpstreamLookups>,
+	cohort: &RolloutCohort,
+	query: &ArtifactQuery<OneConfigVersionInfo>
+) -> Result<ResolvedOneConfig, ErrorResponse> {
+	let mut artifacts = Vec::<ResolvedArtifact>::new();
+	let repository = query.repository();
+	let minecraft_version = &query.version_info.version;
+	let instance_query = format!(
+		"version={minecraft_version}&loader={loader}&repository={repository}",
+		loader = query.version_info.loader
 	);
 
-	let oneconfig_checksum = match maven::fetch_checksum(&state.client, &latest_oneconfig_url).await
+	// Variants of the Minecraft version the requested one is mapped to, and of
+	// fallback mod loaders, are only tried if there is no variant for the
+	// requested ones at all
+	let mut minecraft_versions = vec![minecraft_version.as_str()];
+	if let Some(mapped) = state.mapped_minecraft_version(minecraft_version)
+		&& mapped != minecraft_version
 	{
-		Ok(checksum) => checksum,
-		Err(e) => {
-			return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!("Error fetching checksum for oneconfig: {e}"));
+		minecraft_versions.push(mapped);
+	}
+	let candidates = minecraft_versions
+		.into_iter()
+		.flat_map(|version| {
+			state
+				.loader_candidates(version, &query.version_info.loader)
+				.into_iter()
+				.map(move |loader| (version, loader))
+		})
+		.collect::<Vec<_>>();
+
+	let mut served = None;
+	// A pin with a single candidate is resolved without checking that it exists
+	let unchecked = query.pin.is_some() && candidates.len() == 1;
+	for (minecraft_version, loader) in &candidates {
+		let variant = format!("{minecraft_version}-{loader}");
+		let version = match query.pin.clone() {
+			Some(pin) if unchecked => Ok(pin),
+			Some(pin) => maven::fetch_maven_metadata(
+				state,
+				lookups,
+				repository,
+				ONECONFIG_GROUP,
+				&variant
+			)
+			.await
+			.map(|_| pin),
+			None =>
+				maven::fetch_latest_artifact(
+					state,
+					lookups,
+					repository,
+					ONECONFIG_GROUP,
+					&variant,
+					query.channel,
+					cohort
+				)
+				.await,
+		};
+
+		match version {
+			Ok(version) => {
+				if !unchecked {
+					state
+						.published_variants
+						.insert(
+							(
+								repository.to_string(),
+								minecraft_version.to_string(),
+								loader.clone()
+							),
+							true
+						)
+						.await;
+				}
+				served = Some((
+					minecraft_version.to_string(),
+					loader.clone(),
+					variant,
+					version
+				));
+				break;
+			}
+			Err(MavenError::MetadataNotFound) => continue,
+			Err(e) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					"Error fetching latest oneconfig version",
+					&instance_query
+				)),
 		}
+	}
+	let Some((minecraft_version, loader, oneconfig_variant, latest_oneconfig_version)) =
+		served
+	else {
+		let nearest = nearest_published_versions(
+			state,
+			lookups,
+			repository,
+			minecraft_version,
+			&query.version_info.loader
+		)
+		.await;
+		return Err(ErrorResponse::InvalidOneConfigVersion {
+			title: INVALID_ONECONFIG_VERSION_TITLE.to_string(),
+			detail: format!(
+				"The requested version {variants} could not be found in the requested \
+				 {repository} repository{suggestion}",
+				variants = candidates
+					.iter()
+					.map(|(version, loader)| format!("{version}-{loader}"))
+					.collect::<Vec<_>>()
+					.join(" or "),
+				suggestion = match nearest.as_slice() {
+					[] => String::new(),
+					[version] =>
+						format!(". The nearest supported Minecraft version is {version}"),
+					versions => format!(
+						". The nearest supported Minecraft versions are {}",
+						versions.join(" and ")
+					)
+				}
+			),
+			instance: format!(
+				"{INVALID_ONECONFIG_VERSION_INSTANCE_PREFIX}?{instance_query}"
+			)
+		});
 	};
 
+	// Add oneconfig itself to the artifacts. Its checksum is resolved through the
+	// internal maven url, as only that one may be sent credentials
+	let latest_oneconfig_version = latest_oneconfig_version.to_string();
+	let oneconfig_file = format!("{oneconfig_variant}-{latest_oneconfig_version}.jar");
+	let oneconfig_path = maven::get_artifact_path(
+		repository,
+		ONECONFIG_GROUP,
+		&oneconfig_variant,
+		&latest_oneconfig_version,
+		&oneconfig_file
+	);
+	let latest_oneconfig_url = state.public_artifact_url(
+		repository,
+		ONECONFIG_GROUP,
+		&oneconfig_variant,
+		&latest_oneconfig_version,
+		&oneconfig_file
+	);
 
-	artifacts.push(ArtifactResponse {
-		group: ONECONFIG_GROUP.to_string(),
-		name: format!(
-			"{}-{}",
-			query.version_info.version, query.version_info.loader
-		),
-		jij: false,
-		checksum: Checksum {
-			r#type: ChecksumType::Sha256,
-			hash: oneconfig_checksum
-		},
-		url: latest_oneconfig_url
+	let internal_oneconfig_url = format!(
+		"{maven_url}{oneconfig_path}",
+		maven_url = state.upstream_url()
+	);
+	let oneconfig_checksum =
+		match maven::fetch_checksum(state, lookups, repository, &internal_oneconfig_url)
+			.await
+		{
+			Ok(checksum) => checksum,
+			Err(e) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					"Error fetching checksum for oneconfig",
+					&instance_query
+				)),
+		};
+	let oneconfig_metadata = if query.metadata {
+		match maven::fetch_jar_contents(
+			state,
+			lookups,
+			repository,
+			&internal_oneconfig_url,
+			&oneconfig_checksum
+		)
+		.await
+		{
+			Ok(contents) => contents.metadata,
+			Err(e) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					"Error reading the metadata of oneconfig",
+					&instance_query
+				)),
+		}
+	} else {
+		None
+	};
+
+	artifacts.push(ResolvedArtifact {
+		version: latest_oneconfig_version.clone(),
+		response: ArtifactResponse {
+			group: ONECONFIG_GROUP.to_string(),
+			name: oneconfig_variant.clone(),
+			jij: false,
+			checksum: Checksum {
+				r#type: ChecksumType::Sha256,
+				hash: oneconfig_checksum
+			},
+			url: latest_oneconfig_url,
+			revoked: state
+				.revocation(
+					ONECONFIG_GROUP,
+					&oneconfig_variant,
+					&latest_oneconfig_version
+				)
+				.map(str::to_string),
+			required: query.installed.as_deref().is_some_and(|installed| {
+				state.update_required(
+					ONECONFIG_GROUP,
+					&oneconfig_variant,
+					installed,
+					Some((&minecraft_version, &loader))
+				)
+			}),
+			metadata: oneconfig_metadata,
+			nested: None,
+			minecraft_version: Some(minecraft_version.clone()),
+			loader: Some(loader.clone())
+		}
 	});
 
 	// Resolve all relevant dependency bundles of the proper oneconfig version
-	let Ok(dependency) = maven::fetch_module_metadata(
-		&state,
+	let dependency = match maven::fetch_module_metadata(
+		state,
+		lookups,
 		repository,
 		ONECONFIG_GROUP,
-		&format!(
-			"{}-{}",
-			query.version_info.version, query.version_info.loader
-		),
-		&latest_oneconfig_version.to_string()
+		&oneconfig_variant,
+		&latest_oneconfig_version
 	)
 	.await
-	else {
-		return HttpResponse::InternalServerError()
-			.content_type("text/plain")
-			.body(format!(
-				"Error fetching module metadata for {}:{}-{}:{}",
-				ONECONFIG_GROUP,
-				query.version_info.version,
-				query.version_info.loader,
-				latest_oneconfig_version
-			));
+	{
+		Ok(dependency) => dependency,
+		Err(e) =>
+			return Err(ErrorResponse::from_maven_error(
+				e,
+				&format!(
+					"Error fetching module metadata for \
+					 {ONECONFIG_GROUP}:{oneconfig_variant}:{latest_oneconfig_version}"
+				),
+				&instance_query
+			)),
 	};
 
-	let mut join_set: JoinSet<Result<ArtifactResponse, anyhow::Error>> = JoinSet::new();
-	let internal_maven_url = state
-		.internal_maven_url
-		.clone()
-		.unwrap_or(state.public_maven_url.clone());
-
+	let mut join_set: JoinSet<Result<ResolvedArtifact, MavenError>> = JoinSet::new();
 	for variant in dependency.variants {
 		let Variant::OneConfigModulesApiElements { dependencies } = variant else {
 			continue;
@@ -195,78 +918,287 @@ async fn oneconfig(
 				continue;
 			}
 
-			let internal_dep_url = maven::get_dep_url(&internal_maven_url, repository, &dep);
-			let dep_url = maven::get_dep_url(&state.public_maven_url, repository, &dep);
-
-			let client = state.client.clone();
-			join_set.spawn(async move {
-				Ok(ArtifactResponse {
-					name: dep.module.clone(),
-					group: dep.group,
-					jij: dep.attributes.jij,
-					checksum: Checksum {
-						r#type: ChecksumType::Sha256,
-						hash: maven::fetch_checksum(&client, &internal_dep_url).await?
-					},
-					url: dep_url
-				})
-			});
+			let internal_dep_url =
+				maven::get_dep_url(state.upstream_url(), repository, &dep);
+			let dep_url = state.public_dep_url(repository, &dep);
+			let revoked = state
+				.revocation(&dep.group, &dep.module, &dep.version.requires)
+				.map(str::to_string);
+
+			let state = state.clone();
+			let lookups = lookups.cloned();
+			let include_metadata = query.metadata;
+			// Spawned tasks don't inherit the span of the request on their own
+			join_set.spawn(
+				async move {
+					let checksum = maven::fetch_checksum(
+						&state,
+						lookups.as_ref(),
+						repository,
+						&internal_dep_url
+					)
+					.await?;
+					let (metadata, nested) = if include_metadata {
+						let contents = maven::fetch_jar_contents(
+							&state,
+							lookups.as_ref(),
+							repository,
+							&internal_dep_url,
+							&checksum
+						)
+						.await?;
+						(
+							contents.metadata,
+							dep.attributes.jij.then_some(contents.nested)
+						)
+					} else {
+						(None, None)
+					};
+
+					Ok(ResolvedArtifact {
+						version: dep.version.requires,
+						response: ArtifactResponse {
+							name: dep.module,
+							group: dep.group,
+							jij: dep.attributes.jij,
+							checksum: Checksum {
+								r#type: ChecksumType::Sha256,
+								hash: checksum
+							},
+							url: dep_url,
+							revoked,
+							required: false,
+							metadata,
+							nested,
+							minecraft_version: None,
+							loader: None
+						}
+					})
+				}
+				.in_current_span()
+			);
 		}
 	}
 
 	// Wait for all deps to be resolved
-	while let Some(Ok(dep)) = join_set.join_next().await {
+	while let Some(dep) = join_set.join_next().await {
 		match dep {
-			Ok(artifact) => artifacts.push(artifact),
-			Err(e) => return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!("Error fetching checksum for dependency: {e}"))
+			Ok(Ok(artifact)) => artifacts.push(artifact),
+			Ok(Err(e)) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					"Error resolving dependency",
+					&instance_query
+				)),
+			Err(e) =>
+				return Err(ErrorResponse::internal_error(
+					format!("Error resolving dependency: {e}"),
+					&instance_query
+				)),
 		}
 	}
 
-	// Convert artifacts to JSON and insert a copy into the cache
-	let Ok(response) = serde_json::to_string(&artifacts) else {
-		return HttpResponse::InternalServerError().body("huh");
-	};
+	// Every artifact apart from oneconfig itself is a dependency
+	histogram!(RESOLVED_DEPENDENCIES, "artifact" => "oneconfig")
+		.record((artifacts.len() - 1) as f64);
 
-	HttpResponse::Ok()
-		.content_type("application/json")
-		.body(response)
+	Ok(ResolvedOneConfig {
+		artifacts,
+		minecraft_version,
+		loader,
+		update_check: UpdateCheck {
+			minecraft_version: query.version_info.version.clone(),
+			loader: query.version_info.loader.to_string(),
+			repository: repository.to_string(),
+			oneconfig_version: latest_oneconfig_version
+		},
+		instance_query
+	})
 }
 
-#[get("/{artifact:stage1|relaunch}")]
-async fn platform_agnostic_artifacts(
-	state: web::Data<ApiData>,
+/// Resolves the latest version of an artifact which is shared by every
+/// Minecraft version and mod loader
+#[utoipa::path(
+	context_path = "/v1/artifacts",
+	tag = "artifacts",
+	params(
+		("artifact" = String, Path, description = "The artifact to resolve, such as stage1 or relaunch"),
+		ArtifactQuery,
+		("X-Client-Id" = Option<String>, Header, description = "A stable identifier of the client, which decides whether it receives versions that are being rolled out")
+	),
+	responses(
+		(status = OK, description = "The resolved artifact", body = ArtifactResponse),
+		(
+			status = "4XX",
+			description = "The artifact does not exist, or the query is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		),
+		(
+			status = "5XX",
+			description = "The artifact could not be resolved from maven",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[get("/{artifact}")]
+pub async fn platform_agnostic_artifacts(
+	state: CurrentApiData,
+	cohort: RolloutCohort,
 	query: web::Query<ArtifactQuery>,
 	path: web::Path<(String,)>
 ) -> impl Responder {
 	let artifact = path.into_inner().0;
-	let repository = if query.snapshots {
-		"snapshots"
-	} else {
-		"releases"
+	let response = match resolve_artifact(&state, None, &cohort, &artifact, &query).await
+	{
+		Ok(response) => response,
+		Err(e) => return e.into()
 	};
-	// Fetch the latest artifact version
-	let latest_stage1_version = match maven::fetch_latest_artifact(
-		&state,
-		repository,
-		ONECONFIG_GROUP,
-		&artifact
+
+	let response = match serde_json::to_string(&response) {
+		Ok(response) => response,
+		Err(e) =>
+			return ErrorResponse::internal_error(
+				format!("Error constructing latest {artifact} version: {e}"),
+				&format!(
+					"artifact={artifact}&repository={repository}",
+					repository = query.repository()
+				)
+			)
+			.into(),
+	};
+
+	HttpResponse::Ok()
+		.content_type("application/json")
+		.body(response)
+}
+
+/// The Minecraft versions closest to the requested one out of those with a
+/// published OneConfig variant for the mod loader or one of its fallbacks, see
+/// [`compatibility::nearest_minecraft_versions`]. They are taken from
+/// [`ApiData::published_variants`], which the versions served by the mappings
+/// are checked into once, so that unsupported versions don't each cause
+/// requests to maven.
+async fn nearest_published_versions(
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
+	repository: &str,
+	requested: &str,
+	loader: &ModLoader
+) -> Vec<String> {
+	let served = state
+		.served_minecraft_versions()
+		.into_iter()
+		.flat_map(|version| {
+			state
+				.loader_candidates(version, loader)
+				.into_iter()
+				.map(move |loader| (version, loader))
+		})
+		.collect::<Vec<_>>();
+	future::join_all(served.into_iter().map(|(version, loader)| async move {
+		let variant = format!("{version}-{loader}");
+		let key = (repository.to_string(), version.to_string(), loader);
+		// Other errors are left to be retried, rather than cached as unpublished
+		let _ = state
+			.published_variants
+			.try_get_with(key, async {
+				match maven::fetch_maven_metadata(
+					state,
+					lookups,
+					repository,
+					ONECONFIG_GROUP,
+					&variant
+				)
+				.await
+				{
+					Ok(_) => Ok(true),
+					Err(MavenError::MetadataNotFound) => Ok(false),
+					Err(e) => Err(e)
+				}
+			})
+			.await;
+	}))
+	.await;
+
+	let published = state
+		.published_variants
+		.iter()
+		.filter(|(key, published)| {
+			let (variant_repository, version, variant_loader) = key.as_ref();
+			*published
+				&& variant_repository == repository
+				&& state
+					.loader_candidates(version, loader)
+					.contains(variant_loader)
+		})
+		.map(|(key, _)| key.1.clone())
+		.collect::<Vec<_>>();
+	compatibility::nearest_minecraft_versions(
+		&published.iter().map(String::as_str).collect::<Vec<_>>(),
+		requested
 	)
-	.await
-	{
+	.into_iter()
+	.map(str::to_string)
+	.collect()
+}
+
+fn unknown_artifact(artifact: &str, repository: &str) -> ErrorResponse {
+	ErrorResponse::UnknownArtifact {
+		title: UNKNOWN_ARTIFACT_TITLE.to_string(),
+		detail: format!("The artifact {artifact} is not served"),
+		instance: format!(
+			"{UNKNOWN_ARTIFACT_INSTANCE_PREFIX}?artifact={artifact}&\
+			 repository={repository}"
+		)
+	}
+}
+
+/// Resolves the version of an artifact shared by every Minecraft version and
+/// mod loader that matches the query, see [`resolve_oneconfig`]
+async fn resolve_artifact<V>(
+	state: &CurrentApiData,
+	lookups: Option<&UpstreamLookups>,
+	cohort: &RolloutCohort,
+	artifact: &str,
+	query: &ArtifactQuery<V>
+) -> Result<ArtifactResponse, ErrorResponse> {
+	let repository = query.repository();
+	let instance_query = format!("artifact={artifact}&repository={repository}");
+
+	if !state.artifacts.iter().any(|served| served == artifact) {
+		return Err(unknown_artifact(artifact, repository));
+	}
+	// Fetch the latest artifact version
+	let latest_stage1_version = match query.pin.clone() {
+		Some(pin) => Ok(pin),
+		None =>
+			maven::fetch_latest_artifact(
+				state,
+				lookups,
+				repository,
+				ONECONFIG_GROUP,
+				artifact,
+				query.channel,
+				cohort
+			)
+			.await,
+	};
+	let latest_stage1_version = match latest_stage1_version {
 		Ok(latest) => latest,
-		Err(e) => {
-			return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!("Error resolving latest {artifact} version: {e}"));
-		}
+		Err(e) =>
+			return Err(ErrorResponse::from_maven_error(
+				e,
+				&format!("Error resolving latest {artifact} version"),
+				&instance_query
+			)),
 	};
 
 	// Resolve URL and checksum
 	let dep = Dependency {
 		group: ONECONFIG_GROUP.to_string(),
-		module: artifact.clone(),
+		module: artifact.to_string(),
 		version: VersionRequirement {
 			requires: latest_stage1_version.to_string()
 		},
@@ -275,52 +1207,62 @@ async fn platform_agnostic_artifacts(
 			artifact_selector: Some(ArtifactSelector {
 				classifier: "all".to_string(),
 				extension: "jar".to_string(),
-				name: artifact.clone()
+				name: artifact.to_string()
 			})
 		})
 	};
 
-	let checksum = match maven::fetch_checksum(
-		&state.client,
-		&maven::get_dep_url(
-			&state
-				.internal_maven_url
-				.clone()
-				.unwrap_or(state.public_maven_url.clone()),
+	let internal_url = maven::get_dep_url(state.upstream_url(), repository, &dep);
+	let checksum =
+		match maven::fetch_checksum(state, lookups, repository, &internal_url).await {
+			Ok(checksum) => checksum,
+			Err(e) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					&format!("Error resolving latest {artifact} version checksum"),
+					&instance_query
+				)),
+		};
+	let metadata = if query.metadata {
+		match maven::fetch_jar_contents(
+			state,
+			lookups,
 			repository,
-			&dep
+			&internal_url,
+			&checksum
 		)
-	)
-	.await
-	{
-		Ok(checksum) => checksum,
-		Err(e) =>
-			return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!(
-					"Error resolving latest {artifact} version checksum: {e}"
+		.await
+		{
+			Ok(contents) => contents.metadata,
+			Err(e) =>
+				return Err(ErrorResponse::from_maven_error(
+					e,
+					&format!("Error reading the metadata of {artifact}"),
+					&instance_query
 				)),
+		}
+	} else {
+		None
 	};
 
-	let response = match serde_json::to_string(&ArtifactResponse {
-		name: artifact.clone(),
+	Ok(ArtifactResponse {
+		name: artifact.to_string(),
 		group: ONECONFIG_GROUP.to_string(),
 		jij: false,
 		checksum: Checksum {
 			r#type: ChecksumType::Sha256,
 			hash: checksum
 		},
-		url: maven::get_dep_url(&state.public_maven_url, repository, &dep)
-	}) {
-		Ok(response) => response,
-		Err(e) => {
-			return HttpResponse::InternalServerError()
-				.content_type("text/plain")
-				.body(format!("Error constructing latest {artifact} version: {e}"));
-		}
-	};
-
-	HttpResponse::Ok()
-		.content_type("application/json")
-		.body(response)
+		url: state.public_dep_url(repository, &dep),
+		revoked: state
+			.revocation(ONECONFIG_GROUP, artifact, &dep.version.requires)
+			.map(str::to_string),
+		required: query.installed.as_deref().is_some_and(|installed| {
+			state.update_required(ONECONFIG_GROUP, artifact, installed, None)
+		}),
+		metadata,
+		nested: None,
+		minecraft_version: None,
+		loader: None
+	})
 }
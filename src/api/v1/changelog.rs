@@ -0,0 +1,214 @@
+use std::{fs, io, path::Path};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::{stream, StreamExt as _};
+use semver::Version;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+	api::v1::{
+		responses::{consts::*, ChangelogEntry, ChangelogResponse, ErrorResponse},
+		ApiData,
+		CurrentApiData
+	},
+	maven,
+	types::oneconfig::ONECONFIG_GROUP
+};
+
+/// How many versions have their release notes fetched at the same time
+const CONCURRENT_FETCHES: usize = 8;
+/// How many versions are listed if the query doesn't set a limit
+const DEFAULT_VERSIONS: usize = 20;
+/// The most versions that can be listed at once, as each one may take a
+/// request to maven
+const MAX_VERSIONS: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangelogQuery {
+	/// Snapshots aren't supported, as their files are published under
+	/// timestamped names
+	#[serde(default)]
+	snapshots: bool,
+	/// If set, only lists the versions after this one, such as the installed
+	/// version
+	#[param(value_type = Option<String>, example = "1.0.0-alpha.40")]
+	from: Option<Version>,
+	/// If set, only lists the versions up to and including this one, such as
+	/// the version being updated to
+	#[param(value_type = Option<String>, example = "1.0.0-alpha.50")]
+	to: Option<Version>,
+	/// How many of the newest matching versions to list, which defaults to 20
+	/// and may be at most 100
+	#[param(example = 20)]
+	limit: Option<usize>
+}
+
+/// Collects the release notes of the versions of an artifact of the OneConfig
+/// group, such as stage1 or 1.8.9-forge
+#[utoipa::path(
+	context_path = "/v1/artifacts",
+	tag = "artifacts",
+	params(
+		("artifact" = String, Path, description = "The artifact to list the release notes of, such as stage1 or 1.8.9-forge"),
+		ChangelogQuery
+	),
+	responses(
+		(status = OK, description = "The release notes of each version, from newest to oldest", body = ChangelogResponse),
+		(
+			status = "4XX",
+			description = "The artifact has no versions, or the query is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		),
+		(
+			status = "5XX",
+			description = "The release notes could not be resolved",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[get("/{artifact}/changelog")]
+pub async fn changelog(
+	state: CurrentApiData,
+	query: web::Query<ChangelogQuery>,
+	path: web::Path<(String,)>
+) -> impl Responder {
+	let artifact = path.into_inner().0;
+	let repository = "releases";
+	let instance_query = format!("artifact={artifact}&repository={repository}");
+
+	let invalid_query = |detail: String| ErrorResponse::InvalidQuery {
+		title: INVALID_QUERY_TITLE.to_string(),
+		detail,
+		instance: format!("{INVALID_QUERY_INSTANCE_PREFIX}?{instance_query}")
+	};
+	if query.snapshots {
+		return invalid_query(
+			"Release notes are only collected for releases, as snapshots are published \
+			 under timestamped names"
+				.to_string()
+		)
+		.into();
+	}
+	let limit = query.limit.unwrap_or(DEFAULT_VERSIONS);
+	if limit > MAX_VERSIONS {
+		return invalid_query(format!(
+			"At most {MAX_VERSIONS} versions can be listed at once, but the limit is \
+			 {limit}"
+		))
+		.into();
+	}
+
+	// The metadata also makes sure that the artifact exists before its name is
+	// used as a path in the changelog directory
+	let metadata = match maven::fetch_maven_metadata(
+		&state,
+		None,
+		repository,
+		ONECONFIG_GROUP,
+		&artifact
+	)
+	.await
+	{
+		Ok(metadata) => metadata,
+		Err(e) =>
+			return ErrorResponse::from_maven_error(
+				e,
+				&format!("Error resolving the versions of {artifact}"),
+				&instance_query
+			)
+			.into(),
+	};
+
+	let mut versions = metadata
+		.versioning
+		.versions
+		.versions
+		.into_iter()
+		.filter_map(|v| Version::parse(&v).ok())
+		.filter(|v| query.from.as_ref().is_none_or(|from| v > from))
+		.filter(|v| query.to.as_ref().is_none_or(|to| v <= to))
+		.collect::<Vec<_>>();
+	versions.sort_unstable_by(|a, b| b.cmp(a));
+	versions.truncate(limit);
+
+	let versions = stream::iter(versions)
+		.map(|version| {
+			let (state, artifact, instance_query) = (&state, &artifact, &instance_query);
+			async move {
+				let version = version.to_string();
+				let notes =
+					fetch_notes(state, repository, artifact, &version, instance_query)
+						.await?;
+				Ok::<_, ErrorResponse>(ChangelogEntry { version, notes })
+			}
+		})
+		.buffered(CONCURRENT_FETCHES)
+		.collect::<Vec<_>>()
+		.await
+		.into_iter()
+		.collect::<Result<Vec<_>, _>>();
+
+	match versions {
+		Ok(versions) => HttpResponse::Ok().json(ChangelogResponse {
+			group: ONECONFIG_GROUP.to_string(),
+			name: artifact,
+			versions
+		}),
+		Err(e) => e.into()
+	}
+}
+
+/// Resolves the release notes of a version, preferring the changelog directory
+/// over the ones published to maven
+async fn fetch_notes(
+	state: &ApiData,
+	repository: &str,
+	artifact: &str,
+	version: &str,
+	instance_query: &str
+) -> Result<Option<String>, ErrorResponse> {
+	if let Some(directory) = state.changelog_directory.clone() {
+		let (artifact, version) = (artifact.to_string(), version.to_string());
+		let notes = web::block(move || read_notes(&directory, &artifact, &version))
+			.await
+			.map_err(io::Error::other)
+			.and_then(|notes| notes)
+			.map_err(|e| {
+				ErrorResponse::internal_error(
+					format!("Error reading the changelog directory: {e}"),
+					instance_query
+				)
+			})?;
+		if notes.is_some() {
+			return Ok(notes);
+		}
+	}
+
+	maven::fetch_changelog(state, None, repository, ONECONFIG_GROUP, artifact, version)
+		.await
+		.map_err(|e| {
+			ErrorResponse::from_maven_error(
+				e,
+				&format!("Error resolving the release notes of {artifact} {version}"),
+				instance_query
+			)
+		})
+}
+
+/// Reads the release notes of a version from the changelog directory, which
+/// holds them as `{artifact}/{version}.md`
+fn read_notes(
+	directory: &Path,
+	artifact: &str,
+	version: &str
+) -> io::Result<Option<String>> {
+	match fs::read_to_string(directory.join(artifact).join(format!("{version}.md"))) {
+		Ok(notes) => Ok(Some(notes)),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e)
+	}
+}
@@ -0,0 +1,53 @@
+use std::io::{self, Write as _};
+
+use actix_web::{
+	http::header::{AcceptEncoding, ContentEncoding, Encoding, Header as _},
+	HttpRequest
+};
+use brotli::enc::BrotliEncoderParams;
+use flate2::{write::GzEncoder, Compression};
+
+/// The encodings that responses can be compressed with, in order of preference
+/// when the client has none
+const SUPPORTED_ENCODINGS: [Encoding; 4] = [
+	Encoding::zstd(),
+	Encoding::brotli(),
+	Encoding::gzip(),
+	Encoding::identity()
+];
+
+/// Negotiates the encoding to respond with using the Accept-Encoding header of
+/// the request. If none of the supported encodings are acceptable, responses
+/// are sent uncompressed anyway.
+pub fn negotiate(req: &HttpRequest) -> ContentEncoding {
+	match AcceptEncoding::parse(req)
+		.ok()
+		.and_then(|accept| accept.negotiate(SUPPORTED_ENCODINGS.iter()))
+	{
+		Some(Encoding::Known(encoding)) => encoding,
+		_ => ContentEncoding::Identity
+	}
+}
+
+/// Compresses a response body with the given encoding. Compressed bodies are
+/// cached, so the highest compression levels are worth the extra time.
+pub fn compress(encoding: ContentEncoding, bytes: &[u8]) -> io::Result<Vec<u8>> {
+	match encoding {
+		ContentEncoding::Zstd => zstd::encode_all(bytes, 19),
+		ContentEncoding::Brotli => {
+			let mut compressed = Vec::new();
+			brotli::BrotliCompress(
+				&mut &bytes[..],
+				&mut compressed,
+				&BrotliEncoderParams::default()
+			)?;
+			Ok(compressed)
+		}
+		ContentEncoding::Gzip => {
+			let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+			encoder.write_all(bytes)?;
+			encoder.finish()
+		}
+		_ => Ok(bytes.to_vec())
+	}
+}
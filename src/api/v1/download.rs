@@ -0,0 +1,184 @@
+use std::{collections::HashSet, sync::Arc};
+
+use actix_web::{
+	get,
+	http::header::{HeaderValue, CACHE_CONTROL, LOCATION},
+	web::{self, ServiceConfig},
+	HttpResponse,
+	Responder
+};
+use metrics::counter;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+	api::v1::{
+		responses::{consts::*, ErrorResponse},
+		ApiData,
+		CurrentApiData
+	},
+	maven::{self, MavenError},
+	metrics::DOWNLOADS,
+	statistics::{Download, Statistics}
+};
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+	|config| {
+		config.service(download);
+	}
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+	/// Whether the file is in the snapshots repository rather than releases
+	#[serde(default)]
+	snapshots: bool
+}
+
+/// Counts a download of an artifact file, then redirects to it in the public
+/// maven repository
+#[utoipa::path(
+	context_path = "/v1",
+	tag = "artifacts",
+	params(
+		("group" = String, Path, description = "The group of the artifact", example = "org.polyfrost.oneconfig"),
+		("artifact" = String, Path, description = "The artifact to download", example = "stage1"),
+		("version" = String, Path, description = "The published version of the artifact", example = "1.0.0"),
+		("file" = String, Path, description = "The file of the artifact version to download", example = "stage1-1.0.0-all.jar"),
+		DownloadQuery
+	),
+	responses(
+		(
+			status = FOUND,
+			description = "Redirects to the file in the public maven repository",
+			headers(("Location" = String, description = "The URL of the file"))
+		),
+		(
+			status = "4XX",
+			description = "The artifact version was never published, or the file does not belong to it",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		),
+		(
+			status = "5XX",
+			description = "The published versions could not be resolved from maven",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[get("/download/{group}/{artifact}/{version}/{file}")]
+pub async fn download(
+	state: CurrentApiData,
+	statistics: Option<web::Data<Statistics>>,
+	path: web::Path<(String, String, String, String)>,
+	query: web::Query<DownloadQuery>
+) -> impl Responder {
+	let (group, artifact, version, file) = path.into_inner();
+	let repository = if query.snapshots {
+		"snapshots"
+	} else {
+		"releases"
+	};
+	let instance_query = format!(
+		"group={group}&artifact={artifact}&version={version}&file={file}&\
+		 repository={repository}"
+	);
+
+	// Only artifacts that this API hands out can be downloaded, so that the
+	// statistics can't be filled with anything else from the repository
+	if !state.serves_downloads(&group) {
+		return ErrorResponse::UnknownDownload {
+			title: UNKNOWN_DOWNLOAD_TITLE.to_string(),
+			detail: format!("Artifacts of the group {group} are not served"),
+			instance: format!("{UNKNOWN_DOWNLOAD_INSTANCE_PREFIX}?{instance_query}")
+		}
+		.into();
+	}
+
+	// Only files of the published artifact version can be downloaded, such as
+	// its jar or a classifier of it, so the redirect can't point anywhere else
+	let prefix = format!("{artifact}-{version}");
+	let belongs_to_version = file
+		.strip_prefix(&prefix)
+		.is_some_and(|rest| rest.starts_with(['.', '-']));
+
+	let key = (repository.to_string(), group.clone(), artifact.clone());
+	let versions = match state.known_versions.get(&key).await {
+		// Versions published since the cached ones were fetched, which other
+		// endpoints may already be handing out, are only found by fetching again
+		Some(versions) if versions.contains(&version) || !belongs_to_version =>
+			Ok(versions),
+		_ => fetch_known_versions(&state, key).await
+	};
+	let versions = match versions {
+		Ok(versions) => versions,
+		Err(e) =>
+			return ErrorResponse::from_maven_error(
+				e,
+				&format!("Error resolving the versions of {group}:{artifact}"),
+				&instance_query
+			)
+			.into(),
+	};
+
+	if !versions.contains(&version) || !belongs_to_version {
+		return ErrorResponse::UnknownDownload {
+			title: UNKNOWN_DOWNLOAD_TITLE.to_string(),
+			detail: format!(
+				"{file} is not a file of the published version {version} of \
+				 {group}:{artifact}"
+			),
+			instance: format!("{UNKNOWN_DOWNLOAD_INSTANCE_PREFIX}?{instance_query}")
+		}
+		.into();
+	}
+
+	counter!(
+		DOWNLOADS,
+		"group" => group.clone(),
+		"artifact" => artifact.clone(),
+		"version" => version.clone()
+	)
+	.increment(1);
+	if let Some(statistics) = statistics {
+		statistics.record_download(Download {
+			group: group.clone(),
+			artifact: artifact.clone(),
+			version: version.clone()
+		});
+	}
+
+	let path = maven::get_artifact_path(repository, &group, &artifact, &version, &file);
+	HttpResponse::Found()
+		.insert_header((
+			LOCATION,
+			format!("{maven_url}{path}", maven_url = state.public_maven_url)
+		))
+		// Redirects which are cached by the client or a proxy wouldn't be counted
+		.insert_header((CACHE_CONTROL, HeaderValue::from_static("no-store")))
+		.finish()
+}
+
+/// Fetches the published versions of an artifact from its maven metadata, and
+/// caches them under the given repository, group and artifact
+async fn fetch_known_versions(
+	state: &ApiData,
+	key: (String, String, String)
+) -> Result<Arc<HashSet<String>>, MavenError> {
+	let (repository, group, artifact) = &key;
+	let metadata =
+		maven::fetch_maven_metadata(state, None, repository, group, artifact).await?;
+	let versions = Arc::new(
+		metadata
+			.versioning
+			.versions
+			.versions
+			.into_iter()
+			.collect::<HashSet<_>>()
+	);
+
+	state.known_versions.insert(key, versions.clone()).await;
+	Ok(versions)
+}
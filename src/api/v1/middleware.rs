@@ -1,22 +1,49 @@
 use actix_web::{
 	body::{BoxBody, EitherBody, MessageBody},
 	dev::{ServiceRequest, ServiceResponse},
-	http::header::{HeaderValue, ETAG, IF_NONE_MATCH},
+	http::{
+		header::{
+			ContentEncoding,
+			HeaderValue,
+			CACHE_CONTROL,
+			CONTENT_ENCODING,
+			ETAG,
+			IF_NONE_MATCH,
+			VARY
+		},
+		Method
+	},
 	middleware::Next,
 	web,
 	HttpResponse
 };
+use metrics::counter;
 use sha2::{Digest as _, Sha256};
+use tracing::{instrument, Span};
 
-use super::{ApiData, CacheKey, CacheValue};
+use super::{
+	compression,
+	ApiData,
+	CacheKey,
+	CacheResult,
+	CacheValue,
+	CurrentApiData,
+	ETagType
+};
+use crate::{
+	metrics::CACHE_LOOKUPS,
+	rollout::{RolloutCohort, CLIENT_ID},
+	statistics::{Statistics, UpdateCheck}
+};
 
 pub async fn etag_middleware(
 	service_request: ServiceRequest,
 	next: Next<impl MessageBody>
 ) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
-	let cache = match service_request.app_data::<web::Data<ApiData>>() {
-		Some(app_data) => app_data.cache.clone(),
-		None => {
+	let state = match CurrentApiData::get(service_request.request()) {
+		// Only GET responses are identified by their path and query alone
+		Some(state) if service_request.method() == Method::GET => state,
+		_ => {
 			// If we don't have ApiData for whatever reason, we can't do much
 			// cache-related Technically this could probably be an unwrap, but this is
 			// cleaner
@@ -28,52 +55,30 @@ pub async fn etag_middleware(
 	};
 	let cache_key = CacheKey {
 		path: service_request.path().to_string(),
-		query: service_request.query_string().to_string()
+		query: service_request.query_string().to_string(),
+		encoding: compression::negotiate(service_request.request()),
+		cohort: RolloutCohort::get(service_request.request(), &state)
+	};
+	let identity_cache_key = CacheKey {
+		encoding: ContentEncoding::Identity,
+		..cache_key.clone()
 	};
 
 	// Get and parse If-None-Match condition if is a valid Sha256 ETag
 	let if_none_match = service_request
 		.headers()
 		.get(IF_NONE_MATCH)
-		.and_then(|v| {
-			base16ct::lower::decode_vec(v)
-				.ok()
-				.and_then(|vv| Some((vv, v.to_str().ok()?.to_owned())))
-		})
-		.and_then(|v| Some((TryInto::<[u8; 32]>::try_into(v.0).ok()?, v.1)));
-
-	// Resolve cache entry with path & query
-	if let Some(cache_value) = cache.get(&cache_key).await {
-		// Short circuit with HttpResponse::NotModified() if the If-None-Match header
-		// matches cache
-		if let Some((if_none_match, etag)) = if_none_match
-			&& cache_value.etag == if_none_match
-		{
-			let mut res = HttpResponse::NotModified()
-				.append_header((ETAG, etag))
-				.body(());
-
-			let headers = res.headers_mut();
-			for (name, value) in cache_value.headers {
-				headers.append(name, value);
-			}
-
-			return Ok(service_request.into_response(res).map_into_right_body());
-		}
+		.and_then(|v| base16ct::lower::decode_vec(v).ok())
+		.and_then(|v| TryInto::<ETagType>::try_into(v).ok());
 
-		let mut res = HttpResponse::Ok()
-			.append_header((
-				ETAG,
-				base16ct::lower::encode_string(cache_value.etag.as_ref())
+	if let Some(cache_value) = lookup(&state, &cache_key, &identity_cache_key).await? {
+		return Ok(service_request
+			.into_response(cached_response(
+				cache_value,
+				if_none_match,
+				CacheResult::Hit
 			))
-			.body(cache_value.response);
-
-		let headers = res.headers_mut();
-		for (name, value) in cache_value.headers {
-			headers.append(name, value);
-		}
-
-		return Ok(service_request.into_response(res).map_into_right_body());
+			.map_into_right_body());
 	}
 
 	// If none of the caching cases were handled, pass through to other handlers
@@ -94,26 +99,192 @@ pub async fn etag_middleware(
 			.map_into_right_body());
 		};
 
-		let etag: [u8; 32] = Sha256::digest(&bytes).into();
-		cache
-			.insert(cache_key, CacheValue {
-				response: bytes.clone(),
-				headers: res.headers().to_owned(),
-				etag
-			})
+		// Attach the Cache-Control header, unless the handler already set one itself.
+		// Server errors should never be cached downstream
+		if !res.headers().contains_key(CACHE_CONTROL) {
+			let cache_control = if res.status().is_server_error() {
+				HeaderValue::from_static("no-store")
+			} else {
+				state.cache_control.clone()
+			};
+			res.headers_mut().insert(CACHE_CONTROL, cache_control);
+		}
+		// The cache key depends on the negotiated encoding, so downstream caches
+		// need to as well
+		res.headers_mut()
+			.append(VARY, HeaderValue::from_static("accept-encoding"));
+		if !state.rollouts.is_empty() {
+			res.headers_mut().append(VARY, HeaderValue::from(CLIENT_ID));
+		}
+
+		// Server errors are usually caused by the upstream maven repository, so they
+		// are retried on the next request rather than cached
+		if res.status().is_server_error() {
+			res.extensions_mut().insert(CacheResult::Miss);
+			return Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes)))
+				.map_into_right_body());
+		}
+
+		let identity = CacheValue {
+			status: res.status(),
+			etag: Sha256::digest(&bytes).into(),
+			response: bytes,
+			headers: res.headers().to_owned(),
+			update_check: res.extensions().get::<UpdateCheck>().cloned()
+		};
+		state
+			.cache
+			.insert(identity_cache_key, identity.clone())
 			.await;
 
-		let etag_str = &mut [0u8; 64];
-		base16ct::lower::encode_str(&etag, etag_str)
-			.expect("etag_str slice was the wrong length");
-		res.headers_mut()
-			.append(ETAG, HeaderValue::from_bytes(etag_str)?);
+		let cache_value = if cache_key.encoding == ContentEncoding::Identity {
+			identity
+		} else {
+			let cache_value = encode_cache_value(identity, cache_key.encoding).await?;
+			state.cache.insert(cache_key, cache_value.clone()).await;
+			cache_value
+		};
 
 		Ok(ServiceResponse::new(
 			req,
-			res.set_body(BoxBody::new(bytes)).map_into_right_body()
-		))
+			cached_response(cache_value, if_none_match, CacheResult::Miss)
+		)
+		.map_into_right_body())
 	} else {
 		response.map(|v| v.map_into_left_body())
 	}
 }
+
+/// Records the [`UpdateCheck`] of every successful response, including the
+/// ones served from the cache or answered with 304 Not Modified
+pub async fn update_check_middleware(
+	service_request: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	let statistics = service_request.app_data::<web::Data<Statistics>>().cloned();
+	let response = next.call(service_request).await?;
+
+	if let Some(statistics) = statistics
+		&& (response.status().is_success() || response.status().is_redirection())
+	{
+		let extensions = response.response().extensions();
+		if let Some(update_check) = extensions.get::<UpdateCheck>() {
+			statistics.record_update_check(update_check.clone());
+		}
+		// Batches answer an update check for each OneConfig query
+		for update_check in extensions.get::<Vec<UpdateCheck>>().into_iter().flatten() {
+			statistics.record_update_check(update_check.clone());
+		}
+	}
+
+	Ok(response)
+}
+
+/// Resolves the cache entry for the request with path, query & encoding
+#[instrument(
+	name = "cache_lookup",
+	skip_all,
+	fields(cache.encoding = cache_key.encoding.as_str(), cache.result)
+)]
+async fn lookup(
+	state: &ApiData,
+	cache_key: &CacheKey,
+	identity_cache_key: &CacheKey
+) -> Result<Option<CacheValue>, actix_web::Error> {
+	let span = Span::current();
+
+	if let Some(cache_value) = state.cache.get(cache_key).await {
+		counter!(CACHE_LOOKUPS, "result" => "hit").increment(1);
+		span.record("cache.result", "hit");
+		return Ok(Some(cache_value));
+	}
+
+	// If only the uncompressed response is cached, compress it rather than
+	// resolving everything again
+	if cache_key.encoding != ContentEncoding::Identity
+		&& let Some(identity) = state.cache.get(identity_cache_key).await
+	{
+		counter!(CACHE_LOOKUPS, "result" => "hit").increment(1);
+		span.record("cache.result", "compressed");
+		let cache_value = encode_cache_value(identity, cache_key.encoding).await?;
+		state
+			.cache
+			.insert(cache_key.clone(), cache_value.clone())
+			.await;
+
+		return Ok(Some(cache_value));
+	}
+
+	counter!(CACHE_LOOKUPS, "result" => "miss").increment(1);
+	span.record("cache.result", "miss");
+	Ok(None)
+}
+
+/// Compresses an uncompressed cache value with the given encoding, which gets
+/// its own ETag as a different representation of the same resource
+async fn encode_cache_value(
+	identity: CacheValue,
+	encoding: ContentEncoding
+) -> Result<CacheValue, actix_web::Error> {
+	// Empty bodies and bodies which are already encoded are left as they are
+	if identity.response.is_empty() || identity.headers.contains_key(CONTENT_ENCODING) {
+		return Ok(identity);
+	}
+
+	let body = identity.response;
+	let compressed = web::block(move || compression::compress(encoding, &body)).await??;
+
+	let mut headers = identity.headers;
+	headers.insert(
+		CONTENT_ENCODING,
+		HeaderValue::from_static(encoding.as_str())
+	);
+
+	Ok(CacheValue {
+		status: identity.status,
+		etag: Sha256::digest(&compressed).into(),
+		response: compressed.into(),
+		headers,
+		update_check: identity.update_check
+	})
+}
+
+/// Builds a response from a cache value, short circuiting with
+/// HttpResponse::NotModified() if the If-None-Match header matches it
+fn cached_response(
+	cache_value: CacheValue,
+	if_none_match: Option<ETagType>,
+	cache_result: CacheResult
+) -> HttpResponse {
+	let etag = base16ct::lower::encode_string(cache_value.etag.as_ref());
+
+	let not_modified = cache_value.status.is_success()
+		&& if_none_match.is_some_and(|if_none_match| cache_value.etag == if_none_match);
+
+	let mut res = if not_modified {
+		HttpResponse::NotModified()
+			.append_header((ETAG, etag))
+			.body(())
+	} else {
+		HttpResponse::build(cache_value.status)
+			.append_header((ETAG, etag))
+			.body(cache_value.response)
+	};
+
+	let headers = res.headers_mut();
+	for (name, value) in cache_value.headers {
+		headers.append(name, value);
+	}
+	if let Some(update_check) = cache_value.update_check {
+		res.extensions_mut().insert(update_check);
+	}
+	res.extensions_mut().insert(
+		if not_modified {
+			CacheResult::NotModified
+		} else {
+			cache_result
+		}
+	);
+
+	res
+}
@@ -1,29 +1,88 @@
 pub mod artifacts;
+pub mod changelog;
+pub mod compression;
+pub mod download;
 pub mod middleware;
+pub mod openapi;
+pub mod problems;
 pub mod responses;
+pub mod statistics;
 
-use std::sync::Arc;
+use std::{
+	collections::{HashMap, HashSet},
+	future::{ready, Ready},
+	ops::Deref,
+	path::PathBuf,
+	sync::{Arc, RwLock}
+};
 
 use actix_web::{
-	http::header::HeaderMap,
-	web::{self, Bytes, ServiceConfig}
+	dev::Payload,
+	error::{
+		ErrorInternalServerError,
+		InternalError,
+		JsonPayloadError,
+		QueryPayloadError
+	},
+	http::{
+		header::{ContentEncoding, HeaderMap, HeaderValue},
+		StatusCode
+	},
+	web::{self, Bytes, ServiceConfig},
+	FromRequest,
+	HttpMessage as _,
+	HttpRequest
 };
-use middleware::etag_middleware;
 use moka::future::Cache;
+use responses::{consts::*, ErrorResponse};
+use semver::Version;
+use serde::Serialize;
+
+use crate::{
+	compatibility::{self, LoaderFallback, MinecraftVersionMapping},
+	jar::JarContents,
+	lock,
+	maven,
+	minimum_version::MinimumVersion,
+	rollout::{Rollout, RolloutCohort},
+	statistics::UpdateCheck,
+	types::{
+		gradle_module_metadata::Dependency,
+		oneconfig::{ModLoader, ONECONFIG_GROUP}
+	},
+	upstream_auth::UpstreamAuth
+};
 
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct CacheKey {
 	pub path: String,
-	pub query: String
+	pub query: String,
+	pub encoding: ContentEncoding,
+	pub cohort: RolloutCohort
 }
 
 pub type ETagType = [u8; 32];
 
 #[derive(Clone)]
 pub struct CacheValue {
+	pub status: StatusCode,
 	pub response: Bytes,
 	pub headers: HeaderMap,
-	pub etag: ETagType
+	pub etag: ETagType,
+	/// The update check the response answered, which is recorded again
+	/// whenever the response is served from the cache
+	pub update_check: Option<UpdateCheck>
+}
+
+/// Whether a response was served from the cache, inserted into the extensions
+/// of the response by the caching middleware
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheResult {
+	Hit,
+	Miss,
+	/// The cached response matched If-None-Match, so only a 304 was sent
+	NotModified
 }
 
 pub struct ApiData {
@@ -31,18 +90,258 @@ pub struct ApiData {
 	pub public_maven_url: String,
 	/// The maven URL prefix to resolve artifacts internally, for example https://172.19.0.3:8080/
 	pub internal_maven_url: Option<String>,
+	/// The URL prefix this API is publicly served at, for example https://api.polyfrost.org/
+	pub public_api_url: Option<String>,
 	/// A reqwest client to use to fetch maven data
 	pub client: Arc<reqwest::Client>,
 	/// The internal cache used to cache artifact responses.
-	pub cache: Cache<CacheKey, CacheValue>
+	pub cache: Cache<CacheKey, CacheValue>,
+	/// The Cache-Control header value to send with API responses. Even
+	/// responses pinned to a release version can change, as they flag revoked
+	/// and required versions from the reloadable configuration.
+	pub cache_control: HeaderValue,
+	/// The artifacts shared by every Minecraft version and mod loader which can
+	/// be resolved, such as stage1
+	pub artifacts: Vec<String>,
+	/// The groups of the artifacts bundled with OneConfig, which can be
+	/// downloaded along with the OneConfig group
+	pub dependency_groups: Vec<String>,
+	/// The credentials sent to the maven repository by repository name, with
+	/// `*` applying to any other repository
+	pub upstream_auth: HashMap<String, UpstreamAuth>,
+	/// The published versions of artifacts by repository, group and artifact,
+	/// used to check downloads without fetching the metadata every time
+	pub known_versions: Cache<(String, String, String), Arc<HashSet<String>>>,
+	/// The versions which are only released to a percentage of clients so far
+	pub rollouts: Vec<Rollout>,
+	/// The reasons that versions were revoked for, by `group:artifact:version`
+	pub revoked: HashMap<String, String>,
+	/// The lowest versions of artifacts that clients may keep using
+	pub minimum_versions: Vec<MinimumVersion>,
+	/// The directory holding release notes as `{artifact}/{version}.md`, which
+	/// take precedence over the ones published to maven
+	pub changelog_directory: Option<PathBuf>,
+	/// What was read from artifact jars by their checksum, which is kept across
+	/// reloads as it can never change
+	pub jar_contents: Cache<String, JarContents>,
+	/// The mod loaders whose OneConfig variants are served when there are none
+	/// for the requested loader
+	pub loader_fallbacks: Vec<LoaderFallback>,
+	/// The ranges of Minecraft versions that are served the OneConfig variant
+	/// of another version
+	pub minecraft_versions: Vec<MinecraftVersionMapping>,
+	/// Whether the OneConfig variants of Minecraft versions and mod loaders are
+	/// published, by repository, as learned from resolving and suggesting them.
+	/// Versions which aren't supported are suggested the published ones.
+	pub published_variants: Cache<(String, String, ModLoader), bool>
+}
+
+impl ApiData {
+	/// The maven URL prefix that artifacts are resolved from
+	pub fn upstream_url(&self) -> &str {
+		self.internal_maven_url
+			.as_deref()
+			.unwrap_or(&self.public_maven_url)
+	}
+
+	/// Whether the artifacts of a group can be downloaded through the download
+	/// endpoint, which are those of OneConfig and of the dependency groups
+	pub fn serves_downloads(&self, group: &str) -> bool {
+		group == ONECONFIG_GROUP || self.dependency_groups.iter().any(|g| g == group)
+	}
+
+	/// The URL that clients download an artifact file from, which is the
+	/// download endpoint of the API if its public URL is known and it serves
+	/// the group, so that the download is counted
+	pub fn public_artifact_url(
+		&self,
+		repository: &str,
+		group: &str,
+		artifact: &str,
+		version: &str,
+		file: &str
+	) -> String {
+		match &self.public_api_url {
+			Some(api_url) if self.serves_downloads(group) => format!(
+				"{api_url}v1/download/{group}/{artifact}/{version}/{file}{query}",
+				query = if repository == "snapshots" {
+					"?snapshots=true"
+				} else {
+					""
+				}
+			),
+			_ => format!(
+				"{maven_url}{path}",
+				maven_url = self.public_maven_url,
+				path =
+					maven::get_artifact_path(repository, group, artifact, version, file)
+			)
+		}
+	}
+
+	/// The URL that clients download a dependency from, see
+	/// [`ApiData::public_artifact_url`]
+	pub fn public_dep_url(&self, repository: &str, dep: &Dependency) -> String {
+		self.public_artifact_url(
+			repository,
+			&dep.group,
+			&dep.module,
+			&dep.version.requires,
+			&maven::get_dep_filename(dep)
+		)
+	}
+
+	/// Why a version of an artifact was revoked, if it was
+	pub fn revocation(&self, group: &str, artifact: &str, version: &str) -> Option<&str> {
+		self.revoked
+			.get(&format!("{group}:{artifact}:{version}"))
+			.map(String::as_str)
+	}
+
+	/// Whether the installed version of an artifact is below one of its
+	/// minimum versions, see [`MinimumVersion::requires_update`]. Versions
+	/// which aren't valid SemVer can't be compared, so they are never required
+	/// to update.
+	pub fn update_required(
+		&self,
+		group: &str,
+		artifact: &str,
+		installed: &str,
+		target: Option<(&str, &ModLoader)>
+	) -> bool {
+		let Ok(installed) = Version::parse(installed) else {
+			return false;
+		};
+
+		self.minimum_versions
+			.iter()
+			.any(|minimum| minimum.requires_update(group, artifact, &installed, target))
+	}
+
+	/// The mod loaders whose OneConfig variants may be served for a Minecraft
+	/// version and mod loader, see [`compatibility::loader_candidates`]
+	pub fn loader_candidates(
+		&self,
+		minecraft_version: &str,
+		loader: &ModLoader
+	) -> Vec<ModLoader> {
+		compatibility::loader_candidates(
+			&self.loader_fallbacks,
+			minecraft_version,
+			loader
+		)
+	}
+
+	/// The Minecraft version whose OneConfig variant is served for the
+	/// requested one, see [`compatibility::mapped_minecraft_version`]
+	pub fn mapped_minecraft_version(&self, requested: &str) -> Option<&str> {
+		compatibility::mapped_minecraft_version(&self.minecraft_versions, requested)
+	}
+
+	/// The Minecraft versions that OneConfig variants are served for, see
+	/// [`compatibility::served_minecraft_versions`]
+	pub fn served_minecraft_versions(&self) -> Vec<&str> {
+		compatibility::served_minecraft_versions(&self.minecraft_versions)
+	}
+
+	/// The credentials to send when resolving artifacts from the given
+	/// repository
+	pub fn upstream_auth(&self, repository: &str) -> Option<&UpstreamAuth> {
+		self.upstream_auth
+			.get(repository)
+			.or_else(|| self.upstream_auth.get("*"))
+	}
+}
+
+/// Holds the current [`ApiData`], which is replaced as a whole when the
+/// configuration is reloaded
+pub struct SharedApiData(RwLock<Arc<ApiData>>);
+
+impl SharedApiData {
+	pub fn new(data: ApiData) -> Self { Self(RwLock::new(Arc::new(data))) }
+
+	pub fn load(&self) -> Arc<ApiData> { lock::read(&self.0).clone() }
+
+	pub fn store(&self, data: ApiData) { *lock::write(&self.0) = Arc::new(data); }
+}
+
+/// Extracts the [`ApiData`] a request is handled with. Once loaded, it is kept
+/// in the request extensions, so that a reload in the middle of a request
+/// doesn't mix up two configurations.
+#[derive(Clone)]
+pub struct CurrentApiData(Arc<ApiData>);
+
+impl CurrentApiData {
+	pub fn get(req: &HttpRequest) -> Option<Self> {
+		if let Some(current) = req.extensions().get::<Self>() {
+			return Some(current.clone());
+		}
+
+		let current = Self(req.app_data::<web::Data<SharedApiData>>()?.load());
+		req.extensions_mut().insert(current.clone());
+		Some(current)
+	}
+}
+
+impl Deref for CurrentApiData {
+	type Target = ApiData;
+
+	fn deref(&self) -> &ApiData { &self.0 }
+}
+
+impl FromRequest for CurrentApiData {
+	type Error = actix_web::Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+		ready(
+			Self::get(req)
+				.ok_or_else(|| ErrorInternalServerError("ApiData is not configured"))
+		)
+	}
 }
 
 pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
 	move |config| {
 		config.service(
 			web::scope("/v1")
-				.wrap(actix_web::middleware::from_fn(etag_middleware))
+				.app_data(web::QueryConfig::default().error_handler(query_error_handler))
+				.app_data(web::JsonConfig::default().error_handler(json_error_handler))
 				.configure(artifacts::configure())
+				.configure(download::configure())
+				.configure(statistics::configure())
+				.configure(problems::configure())
+				.configure(openapi::configure())
 		);
 	}
 }
+
+/// Responds to queries which could not be deserialized with a problem, rather
+/// than the default plain text error
+fn query_error_handler(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+	let response = ErrorResponse::InvalidQuery {
+		title: INVALID_QUERY_TITLE.to_string(),
+		detail: err.to_string(),
+		instance: format!(
+			"{INVALID_QUERY_INSTANCE_PREFIX}?{query}",
+			query = req.query_string()
+		)
+	};
+
+	InternalError::from_response(err, response.into()).into()
+}
+
+/// Responds to JSON bodies which could not be deserialized with a problem, see
+/// [`query_error_handler`]
+fn json_error_handler(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+	let response = ErrorResponse::InvalidBody {
+		title: INVALID_BODY_TITLE.to_string(),
+		detail: err.to_string(),
+		instance: format!(
+			"{INVALID_BODY_INSTANCE_PREFIX}?{query}",
+			query = req.query_string()
+		)
+	};
+
+	InternalError::from_response(err, response.into()).into()
+}
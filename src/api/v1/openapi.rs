@@ -0,0 +1,75 @@
+use std::sync::LazyLock;
+
+use actix_web::{
+	get,
+	http::header::CACHE_CONTROL,
+	web::ServiceConfig,
+	HttpResponse,
+	Responder
+};
+use utoipa::OpenApi;
+
+use crate::{
+	api::v1::{artifacts, changelog, download, problems, statistics, CurrentApiData},
+	types::oneconfig::{Channel, ModLoader}
+};
+
+/// The OpenAPI specification of the v1 API, generated from the handlers and
+/// the types they accept and respond with
+#[derive(OpenApi)]
+#[openapi(
+	info(
+		title = "Polyfrost API",
+		description = "Resolves Polyfrost artifacts, primarily for update checking and \
+		               downloading"
+	),
+	servers((url = "https://api.polyfrost.org")),
+	paths(
+		artifacts::oneconfig,
+		artifacts::oneconfig_updates,
+		artifacts::batch,
+		artifacts::platform_agnostic_artifacts,
+		changelog::changelog,
+		download::download,
+		statistics::update_checks,
+		statistics::downloads,
+		problems::problem_types,
+		problems::problem_type,
+		specification
+	),
+	components(schemas(ModLoader, Channel)),
+	tags(
+		(name = "artifacts", description = "Resolving artifacts and their checksums"),
+		(name = "statistics", description = "Aggregate counts of update checks and downloads"),
+		(name = "problems", description = "Documentation of RFC9457 problem types"),
+		(name = "meta", description = "Documentation of the API itself")
+	)
+)]
+pub struct ApiDoc;
+
+static SPECIFICATION: LazyLock<utoipa::openapi::OpenApi> = LazyLock::new(ApiDoc::openapi);
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+	|config| {
+		config.service(specification);
+
+		#[cfg(feature = "swagger-ui")]
+		config.service(
+			utoipa_swagger_ui::SwaggerUi::new("/docs/{_:.*}")
+				.config(utoipa_swagger_ui::Config::new(["/v1/openapi.json"]))
+		);
+	}
+}
+
+/// Serves this OpenAPI specification
+#[utoipa::path(
+	context_path = "/v1",
+	tag = "meta",
+	responses((status = OK, description = "The OpenAPI specification", content_type = "application/json"))
+)]
+#[get("/openapi.json")]
+pub async fn specification(state: CurrentApiData) -> impl Responder {
+	HttpResponse::Ok()
+		.insert_header((CACHE_CONTROL, state.cache_control.clone()))
+		.json(&*SPECIFICATION)
+}
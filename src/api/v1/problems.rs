@@ -0,0 +1,200 @@
+use actix_web::{
+	get,
+	http::header::{Accept, ContentType, Header as _, CACHE_CONTROL, VARY},
+	mime,
+	routes,
+	web::{self, ServiceConfig},
+	HttpRequest,
+	HttpResponse,
+	Responder
+};
+
+use crate::api::v1::{
+	responses::{
+		consts::*,
+		ErrorResponse,
+		ProblemDocumentation,
+		ProblemDocumentationResponse
+	},
+	ApiData,
+	CurrentApiData
+};
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+	|config| {
+		config.service(
+			web::scope("/problems")
+				.service(problem_types)
+				.service(problem_type)
+		);
+	}
+}
+
+/// Lists the documentation of every problem type, as HTML or JSON depending on
+/// the Accept header
+#[utoipa::path(
+	context_path = "/v1/problems",
+	tag = "problems",
+	responses(
+		(status = OK, description = "The documentation of every problem type", content(
+			([ProblemDocumentationResponse] = "application/json"),
+			(String = "text/html")
+		))
+	)
+)]
+#[get("")]
+pub async fn problem_types(state: CurrentApiData, req: HttpRequest) -> impl Responder {
+	let docs = ErrorResponse::DOCUMENTATION;
+
+	if prefers_html(&req) {
+		let items = docs
+			.iter()
+			.map(|doc| {
+				format!(
+					"<li><a href=\"problems/{name}\">{title}</a> ({status})</li>",
+					name = doc.name(),
+					title = escape_html(doc.title),
+					status = doc.status
+				)
+			})
+			.collect::<String>();
+
+		documentation_response(
+			&state,
+			ContentType::html(),
+			html_page("Problem types", &format!("<ul>{items}</ul>"))
+		)
+	} else {
+		let docs = docs
+			.iter()
+			.map(ProblemDocumentationResponse::from)
+			.collect::<Vec<_>>();
+
+		match serde_json::to_string(&docs) {
+			Ok(body) => documentation_response(&state, ContentType::json(), body),
+			Err(e) => ErrorResponse::internal_error(
+				format!("Error constructing problem type documentation: {e}"),
+				""
+			)
+			.into()
+		}
+	}
+}
+
+/// Documents a single problem type, as HTML or JSON depending on the Accept
+/// header. Problem instance URIs are answered with the same documentation.
+#[utoipa::path(
+	get,
+	path = "/v1/problems/{type}",
+	tag = "problems",
+	params(("type" = String, Path, description = "The name of the problem type, such as checksum-missing")),
+	responses(
+		(status = OK, description = "The documentation of the problem type", content(
+			(ProblemDocumentationResponse = "application/json"),
+			(String = "text/html")
+		)),
+		(
+			status = NOT_FOUND,
+			description = "The problem type does not exist",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[routes]
+#[get("/{type}")]
+#[get("/{type}/instance")]
+pub async fn problem_type(
+	state: CurrentApiData,
+	req: HttpRequest,
+	path: web::Path<(String,)>
+) -> impl Responder {
+	let name = path.into_inner().0;
+	let Some(doc) = ErrorResponse::DOCUMENTATION
+		.iter()
+		.find(|doc| doc.name() == name)
+	else {
+		return ErrorResponse::UnknownProblemType {
+			title: UNKNOWN_PROBLEM_TYPE_TITLE.to_string(),
+			detail: format!("There is no problem type named {name}"),
+			instance: format!("{UNKNOWN_PROBLEM_TYPE_INSTANCE_PREFIX}?type={name}")
+		}
+		.into();
+	};
+
+	if prefers_html(&req) {
+		documentation_response(&state, ContentType::html(), problem_type_html(doc))
+	} else {
+		match serde_json::to_string(&ProblemDocumentationResponse::from(doc)) {
+			Ok(body) => documentation_response(&state, ContentType::json(), body),
+			Err(e) => ErrorResponse::internal_error(
+				format!("Error constructing problem type documentation: {e}"),
+				&format!("type={name}")
+			)
+			.into()
+		}
+	}
+}
+
+/// Whether the client prefers an HTML page over JSON, such as when a problem
+/// type URI is opened in a browser
+fn prefers_html(req: &HttpRequest) -> bool {
+	let Ok(accept) = Accept::parse(req) else {
+		return false;
+	};
+
+	accept
+		.ranked()
+		.into_iter()
+		.find(|mime| {
+			matches!(
+				(mime.type_(), mime.subtype()),
+				(mime::TEXT, mime::HTML) | (mime::APPLICATION, mime::JSON)
+			)
+		})
+		.is_some_and(|mime| mime.subtype() == mime::HTML)
+}
+
+fn documentation_response(
+	state: &ApiData,
+	content_type: ContentType,
+	body: String
+) -> HttpResponse {
+	HttpResponse::Ok()
+		.content_type(content_type)
+		.insert_header((CACHE_CONTROL, state.cache_control.clone()))
+		.insert_header((VARY, "accept"))
+		.body(body)
+}
+
+fn problem_type_html(doc: &ProblemDocumentation) -> String {
+	let paragraphs = doc
+		.paragraphs()
+		.map(|paragraph| format!("<p>{}</p>", escape_html(&paragraph)))
+		.collect::<String>();
+
+	html_page(
+		doc.title,
+		&format!(
+			"<p><code>{type}</code><br>HTTP status {status}</p>{paragraphs}",
+			r#type = escape_html(doc.r#type),
+			status = doc.status
+		)
+	)
+}
+
+fn html_page(title: &str, body: &str) -> String {
+	format!(
+		"<!DOCTYPE html><html lang=\"en\"><head><meta \
+		 charset=\"utf-8\"><title>{title}</title></head><body><h1>{title}</h1>{body}</\
+		 body></html>",
+		title = escape_html(title)
+	)
+}
+
+fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
@@ -1,52 +1,574 @@
+use std::collections::BTreeMap;
+
 use actix_web::{http::StatusCode, HttpResponse, HttpResponseBuilder};
 use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{maven::MavenError, types::oneconfig::ModLoader};
 
 pub mod consts {
 	pub const INVALID_ONECONFIG_VERSION_TITLE: &str =
 		"The requested OneConfig version could not be found";
 	pub const INVALID_ONECONFIG_VERSION_INSTANCE_PREFIX: &str =
 		"https://api.polyfrost.org/v1/problems/invalid-oneconfig-version/instance";
+	pub const UPSTREAM_UNAVAILABLE_TITLE: &str =
+		"The upstream maven repository could not be reached";
+	pub const UPSTREAM_UNAVAILABLE_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/upstream-unavailable/instance";
+	pub const INVALID_UPSTREAM_RESPONSE_TITLE: &str =
+		"The upstream maven repository returned an invalid response";
+	pub const INVALID_UPSTREAM_RESPONSE_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/invalid-upstream-response/instance";
+	pub const CHECKSUM_MISSING_TITLE: &str =
+		"The checksum of a resolved artifact could not be found";
+	pub const CHECKSUM_MISSING_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/checksum-missing/instance";
+	pub const MODULE_METADATA_MISSING_TITLE: &str =
+		"The module metadata of the resolved artifact could not be found";
+	pub const MODULE_METADATA_MISSING_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/module-metadata-missing/instance";
+	pub const NO_VALID_VERSIONS_TITLE: &str =
+		"The requested artifact does not have any valid versions";
+	pub const NO_VALID_VERSIONS_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/no-valid-versions/instance";
+	pub const ARTIFACT_MISMATCH_TITLE: &str =
+		"The upstream maven repository returned metadata for a different artifact";
+	pub const ARTIFACT_MISMATCH_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/artifact-mismatch/instance";
+	pub const UNKNOWN_ARTIFACT_TITLE: &str = "The requested artifact is not served";
+	pub const UNKNOWN_ARTIFACT_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/unknown-artifact/instance";
+	pub const INVALID_QUERY_TITLE: &str = "The request query parameters are invalid";
+	pub const INVALID_QUERY_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/invalid-query/instance";
+	pub const UNKNOWN_DOWNLOAD_TITLE: &str =
+		"The requested file is not part of a published artifact version";
+	pub const UNKNOWN_DOWNLOAD_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/unknown-download/instance";
+	pub const INVALID_BODY_TITLE: &str = "The request body is invalid";
+	pub const INVALID_BODY_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/invalid-body/instance";
+	pub const RATE_LIMITED_TITLE: &str = "Too many requests were made by the client";
+	pub const RATE_LIMITED_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/rate-limited/instance";
+	pub const STATISTICS_DISABLED_TITLE: &str = "Statistics are not collected";
+	pub const STATISTICS_DISABLED_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/statistics-disabled/instance";
+	pub const INTERNAL_ERROR_TITLE: &str =
+		"An internal error occurred while handling the request";
+	pub const INTERNAL_ERROR_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/internal-error/instance";
+	pub const UNKNOWN_PROBLEM_TYPE_TITLE: &str =
+		"The requested problem type does not exist";
+	pub const UNKNOWN_PROBLEM_TYPE_INSTANCE_PREFIX: &str =
+		"https://api.polyfrost.org/v1/problems/unknown-problem-type/instance";
 }
 
-#[derive(Serialize)]
+use consts::*;
+
+#[derive(Serialize, ToSchema)]
 pub struct ArtifactResponse {
 	pub group: String,
 	pub name: String,
 	pub jij: bool,
 	pub checksum: Checksum,
-	pub url: String // signatures: TODO
+	pub url: String, // signatures: TODO
+	/// If set, why this version was revoked. It is only resolved because it was
+	/// pinned, or is bundled with the resolved version, and should be moved off
+	/// of.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub revoked: Option<String>,
+	/// Whether the installed version sent by the client is below the minimum
+	/// version, so this one must be installed
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub required: bool,
+	/// The metadata of the mod in the jar, if it was requested and the jar
+	/// declares any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub metadata: Option<ModMetadata>,
+	/// The jars nested in the jar of a `jij` artifact, if metadata was
+	/// requested
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub nested: Option<Vec<NestedJar>>,
+	/// The Minecraft version that OneConfig itself was built for, which differs
+	/// from the requested one if it was mapped to a compatible version
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub minecraft_version: Option<String>,
+	/// The mod loader that OneConfig itself was built for, which differs from
+	/// the requested one if its variant fell back to another loader
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub loader: Option<ModLoader>
+}
+
+/// A jar nested in an artifact jar, which the mod loader loads along with it
+#[derive(Serialize, Clone, ToSchema)]
+pub struct NestedJar {
+	#[schema(example = "META-INF/jars/polyui-2.0.0.jar")]
+	pub path: String,
+	/// The checksum of the nested jar, unless it is declared but missing, or is
+	/// too large to read
+	pub checksum: Option<Checksum>,
+	/// The ID of the mod in the nested jar, if it declares one
+	pub id: Option<String>
+}
+
+/// The metadata a jar declares about the mod in it
+#[derive(Serialize, Clone, ToSchema)]
+pub struct ModMetadata {
+	#[schema(example = "oneconfig")]
+	pub id: Option<String>,
+	#[schema(example = "OneConfig")]
+	pub name: Option<String>,
+	pub description: Option<String>,
+	pub dependencies: Vec<ModDependency>,
+	/// The Java version that the classes of the jar were compiled for
+	#[schema(example = 8)]
+	pub java_version: Option<u16>,
+	pub source: MetadataSource
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, ToSchema)]
+pub struct ModDependency {
+	#[schema(example = "minecraft")]
+	pub id: String,
+	/// The versions of the dependency which are supported, in the format of the
+	/// mod loader
+	#[schema(example = ">=1.20.4")]
+	pub version: Option<String>,
+	/// Whether the mod can't be loaded without the dependency
+	pub required: bool
+}
+
+/// The file that the metadata of a jar was read from
+#[derive(Serialize, Clone, ToSchema)]
+pub enum MetadataSource {
+	#[serde(rename = "fabric.mod.json")]
+	Fabric,
+	#[serde(rename = "META-INF/mods.toml")]
+	ModsToml,
+	#[serde(rename = "mcmod.info")]
+	McmodInfo,
+	#[serde(rename = "META-INF/MANIFEST.MF")]
+	Manifest
+}
+
+#[derive(Serialize, Clone, ToSchema)]
 pub struct Checksum {
 	pub r#type: ChecksumType,
 	pub hash: String
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, ToSchema)]
 pub enum ChecksumType {
 	#[serde(rename = "SHA-256")]
 	Sha256
 }
 
-/// An enum of error responses following RFC9457
-#[derive(Serialize)]
-#[serde(tag = "type")]
-pub enum ErrorResponse {
-	#[serde(rename = "https://api.polyfrost.org/v1/problems/invalid-oneconfig-version")]
+/// The release notes of the versions of an artifact, from newest to oldest
+#[derive(Serialize, ToSchema)]
+pub struct ChangelogResponse {
+	pub group: String,
+	pub name: String,
+	pub versions: Vec<ChangelogEntry>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChangelogEntry {
+	pub version: String,
+	/// The Markdown release notes of the version, unless none were published
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub notes: Option<String>
+}
+
+/// The results of the queries of a batch, in the same order as the queries
+#[derive(Serialize, ToSchema)]
+pub struct BatchResponse {
+	pub results: Vec<BatchResult>
+}
+
+/// The result of a query of a batch, which holds either the resolved artifacts
+/// or the problem that prevented resolving them
+#[derive(Serialize, ToSchema)]
+pub struct BatchResult {
+	/// The status that resolving the query on its own would have responded with
+	pub status: u16,
+	/// OneConfig along with its bundled artifacts, or the single shared
+	/// artifact that was queried
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub artifacts: Option<Vec<ArtifactResponse>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub problem: Option<ErrorResponse>
+}
+
+/// The changes which bring installed artifacts up to date with a OneConfig
+/// version
+#[derive(Serialize, ToSchema)]
+pub struct ArtifactDiffResponse {
+	/// The OneConfig version the installed artifacts were compared to
+	pub oneconfig_version: String,
+	/// The Minecraft version and mod loader of the OneConfig variant the
+	/// installed artifacts were compared to, which differ from the requested
+	/// ones if they were mapped or fell back to others
+	pub minecraft_version: String,
+	pub loader: ModLoader,
+	/// Whether any of the updates is required, so the client should apply them
+	/// before continuing to load
+	pub required: bool,
+	/// The artifacts to add, update or remove, which is empty if everything
+	/// installed is up to date
+	pub changes: Vec<ArtifactChange>,
+	/// The installed versions which were revoked, even if they are still the
+	/// resolved ones, so that clients can warn about them
+	pub revoked: Vec<RevokedArtifact>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RevokedArtifact {
+	pub group: String,
+	pub name: String,
+	pub version: String,
+	/// Why the version was revoked
+	pub reason: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArtifactChange {
+	pub action: ChangeAction,
+	pub group: String,
+	pub name: String,
+	/// The installed version, if the client sent it
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub installed_version: Option<String>,
+	/// The version to install, unless the artifact is removed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub version: Option<String>,
+	/// The artifact to install, unless it is removed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub artifact: Option<ArtifactResponse>,
+	/// Why the change is needed
+	pub reason: String,
+	/// Whether the installed version is below the minimum version of the
+	/// artifact, so the update must be applied
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub required: bool
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+	Add,
+	Update,
+	Remove
+}
+
+/// An aggregate count of update checks or downloads
+#[derive(Serialize, ToSchema)]
+pub struct StatisticsCount {
+	/// The values of the fields the counts were grouped by, such as
+	/// `minecraft_version`
+	#[serde(flatten)]
+	pub fields: BTreeMap<String, String>,
+	pub count: u64
+}
+
+/// Defines [`ErrorResponse`] along with the documentation of each of its
+/// variants, which is served at the problem type URIs. This way the docs are
+/// always generated from the problems that can actually be returned.
+macro_rules! error_responses {
+	($(
+		$(#[doc = $doc:literal])+
+		$variant:ident {
+			type: $uri:tt,
+			title: $title:ident,
+			status: $status:ident
+		}
+	),* $(,)?) => {
+		/// An enum of error responses following RFC9457
+		#[derive(Serialize, ToSchema)]
+		#[serde(tag = "type")]
+		pub enum ErrorResponse {
+			$(
+				$(#[doc = $doc])+
+				#[serde(rename = $uri)]
+				$variant {
+					title: String,
+					detail: String,
+					instance: String
+				}
+			),*
+		}
+
+		impl ErrorResponse {
+			/// The documentation of every problem type
+			pub const DOCUMENTATION: &[ProblemDocumentation] = &[$(
+				ProblemDocumentation {
+					r#type: $uri,
+					title: $title,
+					status: StatusCode::$status,
+					description: concat!($($doc, "\n"),+)
+				}
+			),*];
+
+			pub fn status(&self) -> StatusCode {
+				match self {
+					$(Self::$variant { .. } => StatusCode::$status),*
+				}
+			}
+		}
+	};
+}
+
+error_responses! {
+	/// The requested combination of Minecraft version and mod loader is not
+	/// supported by OneConfig, or is not published in the requested repository.
+	///
+	/// Check that the version and loader are correct, or try the snapshots
+	/// repository instead. The detail member suggests the nearest supported
+	/// Minecraft versions, if they are known.
 	InvalidOneConfigVersion {
-		title: String,
-		detail: String,
-		instance: String
+		type: "https://api.polyfrost.org/v1/problems/invalid-oneconfig-version",
+		title: INVALID_ONECONFIG_VERSION_TITLE,
+		status: NOT_FOUND
+	},
+	/// The maven repository that artifacts are resolved from could not be
+	/// reached, timed out, or responded with a server error.
+	///
+	/// This is usually temporary, so the request can be retried later.
+	UpstreamUnavailable {
+		type: "https://api.polyfrost.org/v1/problems/upstream-unavailable",
+		title: UPSTREAM_UNAVAILABLE_TITLE,
+		status: SERVICE_UNAVAILABLE
+	},
+	/// The maven repository responded with metadata that could not be parsed,
+	/// or with an unexpected status.
+	InvalidUpstreamResponse {
+		type: "https://api.polyfrost.org/v1/problems/invalid-upstream-response",
+		title: INVALID_UPSTREAM_RESPONSE_TITLE,
+		status: BAD_GATEWAY
+	},
+	/// The SHA-256 checksum of an artifact could not be found in the maven
+	/// repository. When a version is pinned, this usually means that the pinned
+	/// version does not exist.
+	ChecksumMissing {
+		type: "https://api.polyfrost.org/v1/problems/checksum-missing",
+		title: CHECKSUM_MISSING_TITLE,
+		status: NOT_FOUND
+	},
+	/// The Gradle module metadata of the resolved artifact version, which lists
+	/// the artifacts bundled with it, could not be found in the maven
+	/// repository.
+	ModuleMetadataMissing {
+		type: "https://api.polyfrost.org/v1/problems/module-metadata-missing",
+		title: MODULE_METADATA_MISSING_TITLE,
+		status: NOT_FOUND
+	},
+	/// The requested artifact has not been published, or none of its published
+	/// versions are valid SemVer versions.
+	NoValidVersions {
+		type: "https://api.polyfrost.org/v1/problems/no-valid-versions",
+		title: NO_VALID_VERSIONS_TITLE,
+		status: NOT_FOUND
+	},
+	/// The maven-metadata.xml file of the requested artifact describes a
+	/// different group or artifact, which means the maven repository is
+	/// misconfigured.
+	ArtifactMismatch {
+		type: "https://api.polyfrost.org/v1/problems/artifact-mismatch",
+		title: ARTIFACT_MISMATCH_TITLE,
+		status: BAD_GATEWAY
+	},
+	/// The requested artifact is not one of the artifacts shared by every
+	/// Minecraft version and mod loader that this backend serves, such as
+	/// stage1.
+	UnknownArtifact {
+		type: "https://api.polyfrost.org/v1/problems/unknown-artifact",
+		title: UNKNOWN_ARTIFACT_TITLE,
+		status: NOT_FOUND
+	},
+	/// The query parameters of the request are missing required values, or
+	/// contain values that are not supported, such as an unknown mod loader.
+	///
+	/// The detail member describes which parameter is invalid.
+	InvalidQuery {
+		type: "https://api.polyfrost.org/v1/problems/invalid-query",
+		title: INVALID_QUERY_TITLE,
+		status: UNPROCESSABLE_ENTITY
+	},
+	/// The request body is not valid JSON, or does not match the schema of the
+	/// endpoint.
+	///
+	/// The detail member describes which value is invalid.
+	InvalidBody {
+		type: "https://api.polyfrost.org/v1/problems/invalid-body",
+		title: INVALID_BODY_TITLE,
+		status: UNPROCESSABLE_ENTITY
+	},
+	/// The requested artifact version has not been published in the requested
+	/// repository, or the requested file does not belong to it.
+	UnknownDownload {
+		type: "https://api.polyfrost.org/v1/problems/unknown-download",
+		title: UNKNOWN_DOWNLOAD_TITLE,
+		status: NOT_FOUND
+	},
+	/// The client made more requests than it is allowed to in a short time.
+	///
+	/// The Retry-After header holds the number of seconds to wait before making
+	/// another request.
+	RateLimited {
+		type: "https://api.polyfrost.org/v1/problems/rate-limited",
+		title: RATE_LIMITED_TITLE,
+		status: TOO_MANY_REQUESTS
+	},
+	/// This backend was started without a statistics database, so it has no
+	/// statistics to report.
+	StatisticsDisabled {
+		type: "https://api.polyfrost.org/v1/problems/statistics-disabled",
+		title: STATISTICS_DISABLED_TITLE,
+		status: NOT_FOUND
+	},
+	/// An unexpected error occurred in the backend itself. If this persists,
+	/// please report it to Polyfrost.
+	InternalError {
+		type: "https://api.polyfrost.org/v1/problems/internal-error",
+		title: INTERNAL_ERROR_TITLE,
+		status: INTERNAL_SERVER_ERROR
+	},
+	/// The requested problem type does not exist, so it has no documentation.
+	UnknownProblemType {
+		type: "https://api.polyfrost.org/v1/problems/unknown-problem-type",
+		title: UNKNOWN_PROBLEM_TYPE_TITLE,
+		status: NOT_FOUND
+	}
+}
+
+/// The documentation of a problem type that an [`ErrorResponse`] can have
+pub struct ProblemDocumentation {
+	pub r#type: &'static str,
+	pub title: &'static str,
+	pub status: StatusCode,
+	pub description: &'static str
+}
+
+impl ProblemDocumentation {
+	/// The last path segment of the problem type URI, such as
+	/// `invalid-oneconfig-version`
+	pub fn name(&self) -> &'static str {
+		self.r#type.rsplit('/').next().unwrap_or(self.r#type)
+	}
+
+	/// The paragraphs of the description, unwrapped from the doc comment lines
+	pub fn paragraphs(&self) -> impl Iterator<Item = String> {
+		self.description
+			.split("\n\n")
+			.map(|paragraph| {
+				paragraph
+					.lines()
+					.map(str::trim)
+					.collect::<Vec<_>>()
+					.join(" ")
+			})
+			.filter(|paragraph| !paragraph.is_empty())
+	}
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProblemDocumentationResponse {
+	pub r#type: &'static str,
+	pub title: &'static str,
+	pub status: u16,
+	pub description: String
+}
+
+impl From<&ProblemDocumentation> for ProblemDocumentationResponse {
+	fn from(value: &ProblemDocumentation) -> Self {
+		Self {
+			r#type: value.r#type,
+			title: value.title,
+			status: value.status.as_u16(),
+			description: value.paragraphs().collect::<Vec<_>>().join("\n\n")
+		}
+	}
+}
+
+impl ErrorResponse {
+	/// Maps an error that occurred while resolving artifacts to the matching
+	/// problem. The `context` describes what was being resolved, and
+	/// `instance_query` identifies this occurrence of the problem.
+	pub fn from_maven_error(
+		error: MavenError,
+		context: &str,
+		instance_query: &str
+	) -> Self {
+		let detail = format!("{context}: {error}");
+
+		match error {
+			MavenError::Reqwest(e)
+				if e.is_connect()
+					|| e.is_timeout()
+					|| e.status().is_some_and(|s| s.is_server_error()) =>
+				Self::UpstreamUnavailable {
+					title: UPSTREAM_UNAVAILABLE_TITLE.to_string(),
+					detail,
+					instance: format!(
+						"{UPSTREAM_UNAVAILABLE_INSTANCE_PREFIX}?{instance_query}"
+					)
+				},
+			MavenError::Reqwest(_)
+			| MavenError::XmlParse(_)
+			| MavenError::JsonParse(_)
+			| MavenError::Semver(_)
+			| MavenError::ArtifactNotFound
+			| MavenError::Jar(_)
+			| MavenError::FileTooLarge(_) => Self::InvalidUpstreamResponse {
+				title: INVALID_UPSTREAM_RESPONSE_TITLE.to_string(),
+				detail,
+				instance: format!(
+					"{INVALID_UPSTREAM_RESPONSE_INSTANCE_PREFIX}?{instance_query}"
+				)
+			},
+			MavenError::ArtifactMismatch => Self::ArtifactMismatch {
+				title: ARTIFACT_MISMATCH_TITLE.to_string(),
+				detail,
+				instance: format!("{ARTIFACT_MISMATCH_INSTANCE_PREFIX}?{instance_query}")
+			},
+			MavenError::NoVersions | MavenError::MetadataNotFound =>
+				Self::NoValidVersions {
+					title: NO_VALID_VERSIONS_TITLE.to_string(),
+					detail,
+					instance: format!(
+						"{NO_VALID_VERSIONS_INSTANCE_PREFIX}?{instance_query}"
+					)
+				},
+			MavenError::ModuleNotFound => Self::ModuleMetadataMissing {
+				title: MODULE_METADATA_MISSING_TITLE.to_string(),
+				detail,
+				instance: format!(
+					"{MODULE_METADATA_MISSING_INSTANCE_PREFIX}?{instance_query}"
+				)
+			},
+			MavenError::ChecksumNotFound => Self::ChecksumMissing {
+				title: CHECKSUM_MISSING_TITLE.to_string(),
+				detail,
+				instance: format!("{CHECKSUM_MISSING_INSTANCE_PREFIX}?{instance_query}")
+			}
+		}
+	}
+
+	/// Creates a problem for errors which are not caused by the client or the
+	/// upstream maven repository
+	pub fn internal_error(detail: String, instance_query: &str) -> Self {
+		Self::InternalError {
+			title: INTERNAL_ERROR_TITLE.to_string(),
+			detail,
+			instance: format!("{INTERNAL_ERROR_INSTANCE_PREFIX}?{instance_query}")
+		}
 	}
 }
 
 impl From<ErrorResponse> for HttpResponse {
 	fn from(value: ErrorResponse) -> Self {
-		HttpResponseBuilder::new(match &value {
-			ErrorResponse::InvalidOneConfigVersion { .. } => StatusCode::NOT_FOUND
-		})
-		.content_type("application/json")
-		.json(value)
+		HttpResponseBuilder::new(value.status())
+			.content_type("application/problem+json")
+			.json(value)
 	}
 }
@@ -0,0 +1,154 @@
+use actix_web::{
+	get,
+	http::header::{HeaderValue, CACHE_CONTROL},
+	web::{self, ServiceConfig},
+	HttpResponse,
+	Responder
+};
+use serde::{
+	de::{DeserializeOwned, IntoDeserializer},
+	Deserialize,
+	Deserializer
+};
+use time::Date;
+use utoipa::IntoParams;
+
+use crate::{
+	api::v1::responses::{consts::*, ErrorResponse, StatisticsCount},
+	statistics::{DownloadField, Field, Statistics, UpdateCheckField}
+};
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+	|config| {
+		config.service(
+			web::scope("/statistics")
+				.service(update_checks)
+				.service(downloads)
+		);
+	}
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(bound = "F: DeserializeOwned")]
+pub struct StatisticsQuery<F: DeserializeOwned> {
+	/// The first day to count, in UTC
+	#[param(value_type = Option<String>, format = Date, example = "2025-01-01")]
+	from: Option<Date>,
+	/// The last day to count, in UTC
+	#[param(value_type = Option<String>, format = Date, example = "2025-01-31")]
+	to: Option<Date>,
+	/// The fields to group the counts by, documented by each endpoint as they
+	/// differ between them
+	#[serde(default, deserialize_with = "comma_separated")]
+	#[param(ignore)]
+	by: Option<Vec<F>>
+}
+
+fn comma_separated<'de, D, F>(deserializer: D) -> Result<Option<Vec<F>>, D::Error>
+where
+	D: Deserializer<'de>,
+	F: DeserializeOwned
+{
+	let Some(value) = Option::<String>::deserialize(deserializer)? else {
+		return Ok(None);
+	};
+
+	value
+		.split(',')
+		.map(|field| {
+			F::deserialize(IntoDeserializer::<D::Error>::into_deserializer(field))
+		})
+		.collect::<Result<_, _>>()
+		.map(Some)
+}
+
+/// Counts the update checks answered with a OneConfig version, by Minecraft
+/// version, mod loader, repository and the OneConfig version resolved
+#[utoipa::path(
+	context_path = "/v1/statistics",
+	tag = "statistics",
+	params(
+		StatisticsQuery<UpdateCheckField>,
+		("by" = Option<String>, Query, description = "Comma separated fields to group the counts by, which are all of them if unset, out of day, minecraft_version, loader, repository and oneconfig_version", example = "minecraft_version,loader")
+	),
+	responses(
+		(status = OK, description = "The counts, from most to least common", body = [StatisticsCount]),
+		(
+			status = "4XX",
+			description = "Statistics are not collected, or the query is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[get("/update-checks")]
+pub async fn update_checks(
+	statistics: Option<web::Data<Statistics>>,
+	query: web::Query<StatisticsQuery<UpdateCheckField>>
+) -> impl Responder {
+	counts(statistics, query.into_inner()).await
+}
+
+/// Counts the downloads made through the download endpoint, by artifact
+/// version
+#[utoipa::path(
+	context_path = "/v1/statistics",
+	tag = "statistics",
+	params(
+		StatisticsQuery<DownloadField>,
+		("by" = Option<String>, Query, description = "Comma separated fields to group the counts by, which are all of them if unset, out of day, group, artifact and version", example = "artifact,version")
+	),
+	responses(
+		(status = OK, description = "The counts, from most to least common", body = [StatisticsCount]),
+		(
+			status = "4XX",
+			description = "Statistics are not collected, or the query is invalid",
+			body = ErrorResponse,
+			content_type = "application/problem+json"
+		)
+	)
+)]
+#[get("/downloads")]
+pub async fn downloads(
+	statistics: Option<web::Data<Statistics>>,
+	query: web::Query<StatisticsQuery<DownloadField>>
+) -> impl Responder {
+	counts(statistics, query.into_inner()).await
+}
+
+async fn counts<F: Field + DeserializeOwned>(
+	statistics: Option<web::Data<Statistics>>,
+	query: StatisticsQuery<F>
+) -> HttpResponse {
+	let instance_query = format!("table={table}", table = F::TABLE);
+	let Some(statistics) = statistics else {
+		return ErrorResponse::StatisticsDisabled {
+			title: STATISTICS_DISABLED_TITLE.to_string(),
+			detail: "The backend was started without --statistics-database".to_string(),
+			instance: format!("{STATISTICS_DISABLED_INSTANCE_PREFIX}?{instance_query}")
+		}
+		.into();
+	};
+
+	let fields = query.by.unwrap_or_else(|| F::ALL.to_vec());
+	let counts =
+		web::block(move || statistics.counts(&fields, query.from, query.to)).await;
+
+	match counts {
+		Ok(Ok(counts)) => HttpResponse::Ok()
+			// Counts change with every update check, so they are always read again
+			.insert_header((CACHE_CONTROL, HeaderValue::from_static("no-cache")))
+			.json(counts),
+		Ok(Err(e)) => ErrorResponse::internal_error(
+			format!("Error reading statistics: {e}"),
+			&instance_query
+		)
+		.into(),
+		Err(e) => ErrorResponse::internal_error(
+			format!("Error reading statistics: {e}"),
+			&instance_query
+		)
+		.into()
+	}
+}
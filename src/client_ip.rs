@@ -0,0 +1,51 @@
+use std::net::{AddrParseError, IpAddr};
+
+use actix_web::{http::header::X_FORWARDED_FOR, HttpRequest};
+use ipnet::IpNet;
+
+/// The proxies which are trusted to report the address of the client they
+/// forward requests for through the X-Forwarded-For header
+#[derive(Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+	fn contains(&self, addr: &IpAddr) -> bool {
+		self.0.iter().any(|net| net.contains(addr))
+	}
+
+	/// Resolves the address of the client which made a request. Starting from
+	/// the peer, X-Forwarded-For is walked back for as long as the hops are
+	/// trusted proxies, so that clients can't spoof their address by sending
+	/// the header themselves.
+	pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+		let mut client = request.peer_addr()?.ip();
+
+		let forwarded = request
+			.headers()
+			.get_all(X_FORWARDED_FOR)
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','))
+			.collect::<Vec<_>>();
+
+		for hop in forwarded.into_iter().rev() {
+			if !self.contains(&client) {
+				break;
+			}
+
+			match hop.trim().parse() {
+				Ok(addr) => client = addr,
+				// Anything before a malformed hop can't be trusted either
+				Err(_) => break
+			}
+		}
+
+		Some(client)
+	}
+}
+
+/// Parses a trusted proxy from either a single address or a CIDR range
+pub fn parse_trusted_proxy(value: &str) -> Result<IpNet, AddrParseError> {
+	value
+		.parse()
+		.or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+}
@@ -0,0 +1,343 @@
+use serde::Deserialize;
+
+use crate::types::oneconfig::ModLoader;
+
+/// Serves the OneConfig variant of another mod loader when there is none for
+/// the requested one, configured in the config file. For example, Quilt can
+/// load the Fabric variant.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LoaderFallback {
+	pub loader: ModLoader,
+	pub fallback: ModLoader,
+	/// If set, the fallback only applies to this Minecraft version
+	pub minecraft_version: Option<String>
+}
+
+impl LoaderFallback {
+	/// The fallbacks used unless the config file lists its own
+	pub fn defaults() -> Vec<Self> {
+		vec![
+			Self {
+				loader: ModLoader::Quilt,
+				fallback: ModLoader::Fabric,
+				minecraft_version: None
+			},
+			// NeoForge was forked from Forge 1.20.1, so it can still load its mods
+			Self {
+				loader: ModLoader::NeoForge,
+				fallback: ModLoader::Forge,
+				minecraft_version: Some("1.20.1".to_string())
+			},
+		]
+	}
+}
+
+/// The mod loaders whose OneConfig variants may be served for a Minecraft
+/// version and mod loader, from the most to the least preferred. The requested
+/// loader always comes first, followed by its fallbacks in the order they are
+/// listed.
+pub fn loader_candidates(
+	fallbacks: &[LoaderFallback],
+	minecraft_version: &str,
+	loader: &ModLoader
+) -> Vec<ModLoader> {
+	let mut candidates = vec![loader.clone()];
+	for fallback in fallbacks {
+		if fallback.loader == *loader
+			&& fallback
+				.minecraft_version
+				.as_deref()
+				.is_none_or(|v| v == minecraft_version)
+			&& !candidates.contains(&fallback.fallback)
+		{
+			candidates.push(fallback.fallback.clone());
+		}
+	}
+	candidates
+}
+
+/// Serves the OneConfig variant of a Minecraft version for a range of other
+/// versions that it also runs on, configured in the config file. The served
+/// versions which are published are also the ones suggested when a version
+/// isn't supported.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MinecraftVersionMapping {
+	/// The first Minecraft version of the range
+	pub from: String,
+	/// The last Minecraft version of the range, which is `from` if unset
+	pub to: Option<String>,
+	/// The Minecraft version whose OneConfig variant is served for the range
+	pub served: String
+}
+
+impl MinecraftVersionMapping {
+	/// The mappings used unless the config file lists its own
+	pub fn defaults() -> Vec<Self> {
+		[("1.8", "1.8.9", "1.8.9"), ("1.20.2", "1.20.4", "1.20.4")]
+			.into_iter()
+			.map(|(from, to, served)| Self {
+				from: from.to_string(),
+				to: Some(to.to_string()),
+				served: served.to_string()
+			})
+			.collect()
+	}
+
+	/// Whether the versions of the mapping can be compared, and `from` comes
+	/// before `to`
+	pub fn is_valid(&self) -> bool {
+		let from = MinecraftVersion::parse(&self.from);
+		let to = MinecraftVersion::parse(self.to.as_deref().unwrap_or(&self.from));
+		matches!((from, to), (Some(from), Some(to)) if from <= to)
+			&& MinecraftVersion::parse(&self.served).is_some()
+	}
+
+	fn contains(&self, version: &MinecraftVersion) -> bool {
+		let from = MinecraftVersion::parse(&self.from);
+		let to = MinecraftVersion::parse(self.to.as_deref().unwrap_or(&self.from));
+		from.is_some_and(|from| from <= *version) && to.is_some_and(|to| *version <= to)
+	}
+}
+
+/// A release version of Minecraft, such as 1.20.4, which is compared by its
+/// numbers. Trailing zeros are dropped, so 1.8 is the same as 1.8.0.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct MinecraftVersion(Vec<u32>);
+
+impl MinecraftVersion {
+	/// Parses a release version, or returns [`None`] for anything else, such as
+	/// snapshots like 24w14a
+	fn parse(version: &str) -> Option<Self> {
+		let mut numbers = version
+			.split('.')
+			.map(|number| number.parse().ok())
+			.collect::<Option<Vec<u32>>>()?;
+		while numbers.last() == Some(&0) {
+			numbers.pop();
+		}
+		Some(Self(numbers))
+	}
+}
+
+/// The Minecraft version whose OneConfig variant is served for the requested
+/// one, from the first mapping whose range contains it
+pub fn mapped_minecraft_version<'a>(
+	mappings: &'a [MinecraftVersionMapping],
+	requested: &str
+) -> Option<&'a str> {
+	let requested = MinecraftVersion::parse(requested)?;
+	mappings
+		.iter()
+		.find(|mapping| mapping.contains(&requested))
+		.map(|mapping| mapping.served.as_str())
+}
+
+/// The Minecraft versions that the mappings serve, without duplicates
+pub fn served_minecraft_versions(mappings: &[MinecraftVersionMapping]) -> Vec<&str> {
+	let mut served = mappings
+		.iter()
+		.map(|mapping| mapping.served.as_str())
+		.collect::<Vec<_>>();
+	served.sort_unstable();
+	served.dedup();
+	served
+}
+
+/// The versions closest to the requested one out of the supported Minecraft
+/// versions, which are the nearest ones before and after it
+pub fn nearest_minecraft_versions<'a>(
+	supported: &[&'a str],
+	requested: &str
+) -> Vec<&'a str> {
+	let Some(requested) = MinecraftVersion::parse(requested) else {
+		return Vec::new();
+	};
+
+	let mut served = supported
+		.iter()
+		.filter_map(|version| Some((MinecraftVersion::parse(version)?, *version)))
+		.collect::<Vec<_>>();
+	served.sort();
+	served.dedup_by(|(a, _), (b, _)| a == b);
+
+	let before = served
+		.iter()
+		.rev()
+		.find(|(version, _)| *version < requested);
+	let after = served.iter().find(|(version, _)| *version > requested);
+	before
+		.into_iter()
+		.chain(after)
+		.map(|(_, served)| *served)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn mapping(from: &str, to: Option<&str>, served: &str) -> MinecraftVersionMapping {
+		MinecraftVersionMapping {
+			from: from.to_string(),
+			to: to.map(str::to_string),
+			served: served.to_string()
+		}
+	}
+
+	#[test]
+	fn parse_drops_trailing_zeros() {
+		assert_eq!(
+			MinecraftVersion::parse("1.8"),
+			MinecraftVersion::parse("1.8.0")
+		);
+		assert_eq!(
+			MinecraftVersion::parse("1.20.0.0"),
+			Some(MinecraftVersion(vec![1, 20]))
+		);
+		assert!(MinecraftVersion::parse("1.8.9") > MinecraftVersion::parse("1.8"));
+		assert!(MinecraftVersion::parse("1.20") > MinecraftVersion::parse("1.8.9"));
+	}
+
+	#[test]
+	fn parse_rejects_snapshots() {
+		for version in ["24w14a", "1.20.5-pre1", "1.21-rc1", "", "1..2"] {
+			assert_eq!(MinecraftVersion::parse(version), None, "{version}");
+		}
+	}
+
+	#[test]
+	fn mapped_version_is_from_the_first_containing_range() {
+		let mappings = [
+			mapping("1.20.2", Some("1.20.4"), "1.20.4"),
+			mapping("1.20", Some("1.20.6"), "1.20.6")
+		];
+		assert_eq!(
+			mapped_minecraft_version(&mappings, "1.20.2"),
+			Some("1.20.4")
+		);
+		assert_eq!(
+			mapped_minecraft_version(&mappings, "1.20.4"),
+			Some("1.20.4")
+		);
+		assert_eq!(
+			mapped_minecraft_version(&mappings, "1.20.5"),
+			Some("1.20.6")
+		);
+		assert_eq!(
+			mapped_minecraft_version(&mappings, "1.20.0"),
+			Some("1.20.6")
+		);
+		assert_eq!(mapped_minecraft_version(&mappings, "1.21"), None);
+		assert_eq!(mapped_minecraft_version(&mappings, "24w14a"), None);
+	}
+
+	#[test]
+	fn mapping_without_to_only_contains_from() {
+		let mappings = [mapping("1.8", None, "1.8.9")];
+		assert_eq!(mapped_minecraft_version(&mappings, "1.8.0"), Some("1.8.9"));
+		assert_eq!(mapped_minecraft_version(&mappings, "1.8.1"), None);
+		assert!(mappings[0].is_valid());
+	}
+
+	#[test]
+	fn default_mappings_are_valid() {
+		let mappings = MinecraftVersionMapping::defaults();
+		assert!(mappings.iter().all(MinecraftVersionMapping::is_valid));
+		assert_eq!(mapped_minecraft_version(&mappings, "1.8"), Some("1.8.9"));
+		assert_eq!(
+			mapped_minecraft_version(&mappings, "1.20.3"),
+			Some("1.20.4")
+		);
+	}
+
+	#[test]
+	fn invalid_mappings_are_detected() {
+		assert!(!mapping("1.20.4", Some("1.20.2"), "1.20.4").is_valid());
+		assert!(!mapping("1.20.2", Some("24w14a"), "1.20.4").is_valid());
+		assert!(!mapping("1.20.2", None, "1.20.4-pre1").is_valid());
+	}
+
+	#[test]
+	fn served_versions_are_deduplicated() {
+		let mappings = [
+			mapping("1.20.2", Some("1.20.4"), "1.20.4"),
+			mapping("1.8", Some("1.8.9"), "1.8.9"),
+			mapping("1.20.1", None, "1.20.4")
+		];
+		assert_eq!(served_minecraft_versions(&mappings), ["1.20.4", "1.8.9"]);
+	}
+
+	#[test]
+	fn nearest_versions_are_before_and_after_the_requested_one() {
+		let supported = ["1.8.9", "1.12.2", "1.16.5", "1.20.4"];
+		assert_eq!(nearest_minecraft_versions(&supported, "1.14"), [
+			"1.12.2", "1.16.5"
+		]);
+		assert_eq!(nearest_minecraft_versions(&supported, "1.7.10"), ["1.8.9"]);
+		assert_eq!(nearest_minecraft_versions(&supported, "1.21"), ["1.20.4"]);
+		assert_eq!(nearest_minecraft_versions(&supported, "1.12.2"), [
+			"1.8.9", "1.16.5"
+		]);
+		assert!(nearest_minecraft_versions(&supported, "24w14a").is_empty());
+	}
+
+	#[test]
+	fn nearest_versions_skip_duplicates_and_snapshots() {
+		let supported = ["1.20", "1.20.0", "24w14a", "1.8.9"];
+		assert_eq!(nearest_minecraft_versions(&supported, "1.19"), [
+			"1.8.9", "1.20"
+		]);
+		assert_eq!(nearest_minecraft_versions(&supported, "1.21"), ["1.20"]);
+	}
+
+	#[test]
+	fn requested_loader_comes_before_its_fallbacks() {
+		let fallbacks = LoaderFallback::defaults();
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.20.4", &ModLoader::Quilt),
+			[ModLoader::Quilt, ModLoader::Fabric]
+		);
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.20.4", &ModLoader::Fabric),
+			[ModLoader::Fabric]
+		);
+	}
+
+	#[test]
+	fn fallbacks_only_apply_to_their_minecraft_version() {
+		let fallbacks = LoaderFallback::defaults();
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.20.1", &ModLoader::NeoForge),
+			[ModLoader::NeoForge, ModLoader::Forge]
+		);
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.20.4", &ModLoader::NeoForge),
+			[ModLoader::NeoForge]
+		);
+	}
+
+	#[test]
+	fn fallbacks_keep_their_order_without_duplicates() {
+		let fallback = |fallback, minecraft_version: Option<&str>| LoaderFallback {
+			loader: ModLoader::NeoForge,
+			fallback,
+			minecraft_version: minecraft_version.map(str::to_string)
+		};
+		let fallbacks = [
+			fallback(ModLoader::Forge, Some("1.20.1")),
+			fallback(ModLoader::NeoForge, None),
+			fallback(ModLoader::Fabric, None),
+			fallback(ModLoader::Forge, None)
+		];
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.20.1", &ModLoader::NeoForge),
+			[ModLoader::NeoForge, ModLoader::Forge, ModLoader::Fabric]
+		);
+		assert_eq!(
+			loader_candidates(&fallbacks, "1.21", &ModLoader::NeoForge),
+			[ModLoader::NeoForge, ModLoader::Fabric, ModLoader::Forge]
+		);
+	}
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, fs, io, path::PathBuf, sync::Arc, time::Duration};
+
+use actix_web::{
+	http::header::{HeaderValue, InvalidHeaderValue},
+	web
+};
+use moka::future::Cache;
+use serde::Deserialize;
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+	api::v1::{ApiData, CacheKey, CacheValue, ETagType, SharedApiData},
+	compatibility::{LoaderFallback, MinecraftVersionMapping},
+	minimum_version::MinimumVersion,
+	rollout::Rollout,
+	upstream_auth::{UpstreamAuth, UpstreamAuthError},
+	AppCommand
+};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+	#[error("Unable to read the config file: {0}")]
+	Read(#[from] io::Error),
+	#[error("Unable to parse the config file: {0}")]
+	Parse(#[from] toml::de::Error),
+	#[error("A Cache-Control value in the config file is not a valid header value: {0}")]
+	InvalidHeaderValue(#[from] InvalidHeaderValue),
+	#[error("{0}")]
+	UpstreamAuth(#[from] UpstreamAuthError),
+	#[error(
+		"The public maven url must not contain credentials, use --upstream-auth instead"
+	)]
+	PublicUrlCredentials,
+	#[error("The rollout of {0} must have a percentage from 0 to 100")]
+	InvalidRolloutPercentage(String),
+	#[error("The revoked version {0} is not written as GROUP:ARTIFACT:VERSION")]
+	InvalidRevocation(String),
+	#[error(
+		"The Minecraft versions mapped to {0} must be release versions, with `from` \
+		 before `to`"
+	)]
+	InvalidMinecraftVersionMapping(String)
+}
+
+/// The settings which can be set in the config file, overriding the flag or
+/// environment variable of the same name. Unlike flags, they are reloaded from
+/// the file when the server receives SIGHUP. Rollouts, revoked versions,
+/// minimum versions, loader fallbacks and Minecraft version mappings can only
+/// be set here.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+	public_maven_url: Option<Url>,
+	internal_maven_url: Option<Url>,
+	public_api_url: Option<Url>,
+	cache_control: Option<String>,
+	cache_ttl: Option<u64>,
+	cache_capacity: Option<u64>,
+	artifacts: Option<Vec<String>>,
+	dependency_groups: Option<Vec<String>>,
+	changelog_directory: Option<PathBuf>,
+	#[serde(default)]
+	rollouts: Vec<Rollout>,
+	/// The reasons that versions were revoked for, by `group:artifact:version`
+	#[serde(default)]
+	revoked: HashMap<String, String>,
+	#[serde(default)]
+	minimum_versions: Vec<MinimumVersion>,
+	/// The fallbacks of mod loaders, which replace the default ones if set
+	loader_fallbacks: Option<Vec<LoaderFallback>>,
+	/// The ranges of Minecraft versions, which replace the default ones if set
+	minecraft_versions: Option<Vec<MinecraftVersionMapping>>
+}
+
+/// The reloadable settings that [`ApiData`] is built from
+#[derive(Clone, PartialEq)]
+pub struct Settings {
+	public_maven_url: Url,
+	internal_maven_url: Option<Url>,
+	public_api_url: Option<Url>,
+	cache_control: HeaderValue,
+	cache_ttl: Duration,
+	cache_capacity: u64,
+	artifacts: Vec<String>,
+	dependency_groups: Vec<String>,
+	changelog_directory: Option<PathBuf>,
+	upstream_auth: HashMap<String, UpstreamAuth>,
+	rollouts: Vec<Rollout>,
+	revoked: HashMap<String, String>,
+	minimum_versions: Vec<MinimumVersion>,
+	loader_fallbacks: Vec<LoaderFallback>,
+	minecraft_versions: Vec<MinecraftVersionMapping>
+}
+
+impl Settings {
+	/// Resolves the settings from the flags, overridden by the config file if
+	/// one was passed
+	pub fn load(args: &AppCommand) -> Result<Self, ConfigError> {
+		let file = match &args.config {
+			Some(path) => toml::from_str(&fs::read_to_string(path)?)?,
+			None => ConfigFile::default()
+		};
+
+		let public_maven_url = file
+			.public_maven_url
+			.unwrap_or_else(|| args.public_maven_url.clone());
+		if !public_maven_url.username().is_empty()
+			|| public_maven_url.password().is_some()
+		{
+			return Err(ConfigError::PublicUrlCredentials);
+		}
+
+		if let Some(rollout) = file
+			.rollouts
+			.iter()
+			.find(|rollout| !(0.0..=100.0).contains(&rollout.percentage))
+		{
+			return Err(ConfigError::InvalidRolloutPercentage(
+				rollout.version.to_string()
+			));
+		}
+
+		if let Some(coordinates) = file.revoked.keys().find(|coordinates| {
+			let parts = coordinates.split(':').collect::<Vec<_>>();
+			parts.len() != 3 || parts.iter().any(|part| part.is_empty())
+		}) {
+			return Err(ConfigError::InvalidRevocation(coordinates.clone()));
+		}
+
+		if let Some(mapping) = file
+			.minecraft_versions
+			.iter()
+			.flatten()
+			.find(|mapping| !mapping.is_valid())
+		{
+			return Err(ConfigError::InvalidMinecraftVersionMapping(
+				mapping.served.clone()
+			));
+		}
+
+		// Credentials are read again on every reload, so they can be rotated
+		let upstream_auth = args
+			.upstream_auth
+			.iter()
+			.map(|(repository, source)| Ok((repository.clone(), source.read()?)))
+			.collect::<Result<_, UpstreamAuthError>>()?;
+
+		Ok(Self {
+			public_maven_url,
+			internal_maven_url: file
+				.internal_maven_url
+				.or_else(|| args.internal_maven_url.clone()),
+			public_api_url: file.public_api_url.or_else(|| args.public_api_url.clone()),
+			cache_control: match file.cache_control {
+				Some(value) => HeaderValue::from_str(&value)?,
+				None => args.cache_control.clone()
+			},
+			cache_ttl: Duration::from_secs(file.cache_ttl.unwrap_or(args.cache_ttl)),
+			cache_capacity: file.cache_capacity.unwrap_or(args.cache_capacity),
+			artifacts: file.artifacts.unwrap_or_else(|| args.artifacts.clone()),
+			dependency_groups: file
+				.dependency_groups
+				.unwrap_or_else(|| args.dependency_groups.clone()),
+			changelog_directory: file
+				.changelog_directory
+				.or_else(|| args.changelog_directory.clone()),
+			upstream_auth,
+			rollouts: file.rollouts,
+			revoked: file.revoked,
+			minimum_versions: file.minimum_versions,
+			loader_fallbacks: file
+				.loader_fallbacks
+				.unwrap_or_else(LoaderFallback::defaults),
+			minecraft_versions: file
+				.minecraft_versions
+				.unwrap_or_else(MinecraftVersionMapping::defaults)
+		})
+	}
+
+	/// Whether the responses built with these settings are identical to the
+	/// ones built with the other settings, meaning their cache can be kept
+	fn same_responses(&self, other: &Self) -> bool {
+		Self {
+			internal_maven_url: other.internal_maven_url.clone(),
+			upstream_auth: other.upstream_auth.clone(),
+			..self.clone()
+		} == *other
+	}
+
+	/// Builds the [`ApiData`] for these settings. The cache of the previous
+	/// [`ApiData`] is kept if the responses it holds are still the same.
+	pub fn api_data(
+		&self,
+		client: Arc<reqwest::Client>,
+		previous: Option<(&Settings, &ApiData)>
+	) -> ApiData {
+		let cache = match previous {
+			Some((settings, data)) if self.same_responses(settings) => data.cache.clone(),
+			_ => Cache::builder()
+				.time_to_live(self.cache_ttl)
+				.weigher(|k: &CacheKey, v: &CacheValue| {
+					(k.path.len()
+						+ k.query.len() + const { std::mem::size_of::<ETagType>() }
+						+ v.response.len() + std::mem::size_of_val(&v.headers))
+					.try_into()
+					.unwrap_or(u32::MAX)
+				})
+				.max_capacity(self.cache_capacity)
+				.build()
+		};
+
+		ApiData {
+			public_maven_url: self.public_maven_url.to_string(),
+			internal_maven_url: self.internal_maven_url.as_ref().map(Url::to_string),
+			public_api_url: self.public_api_url.as_ref().map(Url::to_string),
+			client,
+			cache,
+			cache_control: self.cache_control.clone(),
+			artifacts: self.artifacts.clone(),
+			dependency_groups: self.dependency_groups.clone(),
+			upstream_auth: self.upstream_auth.clone(),
+			known_versions: Cache::builder()
+				.time_to_live(self.cache_ttl)
+				.max_capacity(1024)
+				.build(),
+			rollouts: self.rollouts.clone(),
+			revoked: self.revoked.clone(),
+			minimum_versions: self.minimum_versions.clone(),
+			changelog_directory: self.changelog_directory.clone(),
+			jar_contents: match previous {
+				Some((_, data)) => data.jar_contents.clone(),
+				None => Cache::builder().max_capacity(4096).build()
+			},
+			loader_fallbacks: self.loader_fallbacks.clone(),
+			minecraft_versions: self.minecraft_versions.clone(),
+			published_variants: Cache::builder()
+				.time_to_live(self.cache_ttl)
+				.max_capacity(1024)
+				.build()
+		}
+	}
+}
+
+/// Reloads the settings whenever the server receives SIGHUP. Requests which are
+/// already being handled keep the [`ApiData`] they started with.
+#[cfg(unix)]
+pub async fn reload_on_sighup(
+	args: AppCommand,
+	mut settings: Settings,
+	data: web::Data<SharedApiData>
+) {
+	use tokio::signal::unix::{signal, SignalKind};
+
+	let mut hangups = match signal(SignalKind::hangup()) {
+		Ok(hangups) => hangups,
+		Err(e) => {
+			tracing::error!("Unable to listen for SIGHUP, reloading is disabled: {e}");
+			return;
+		}
+	};
+
+	while hangups.recv().await.is_some() {
+		let reloaded = match Settings::load(&args) {
+			Ok(reloaded) => reloaded,
+			Err(e) => {
+				tracing::error!(
+					"Unable to reload the configuration, keeping the current one: {e}"
+				);
+				continue;
+			}
+		};
+
+		if reloaded == settings {
+			tracing::info!("Reloaded the configuration, nothing changed");
+			continue;
+		}
+
+		let current = data.load();
+		let kept_cache = reloaded.same_responses(&settings);
+		data.store(
+			reloaded.api_data(current.client.clone(), Some((&settings, &current)))
+		);
+		settings = reloaded;
+
+		tracing::info!(kept_cache, "Reloaded the configuration");
+	}
+}
@@ -0,0 +1,147 @@
+use std::{
+	error::Error as _,
+	time::{Duration, Instant}
+};
+
+use actix_web::{
+	get,
+	http::header::{HeaderValue, CACHE_CONTROL},
+	web::{self, ServiceConfig},
+	HttpResponse,
+	Responder
+};
+use futures_util::future::join_all;
+use serde::Serialize;
+
+use crate::{api::v1::CurrentApiData, maven};
+
+/// How the readiness of the maven repositories is probed
+pub struct Readiness {
+	/// The path of a maven-metadata.xml file, relative to the maven root url,
+	/// which is fetched from every configured maven repository
+	pub probe_path: String,
+	/// How long each repository has to respond before it is considered down
+	pub timeout: Duration
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+	Ok,
+	Unavailable
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+	status: Status
+}
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+	status: Status,
+	upstreams: Vec<UpstreamStatus>
+}
+
+#[derive(Serialize)]
+struct UpstreamStatus {
+	/// Either `public` or `internal`, matching the maven url it was fetched
+	/// from
+	name: &'static str,
+	url: String,
+	status: Status,
+	latency_ms: f64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>
+}
+
+/// The routes of the probes, which are never limited by the default rate limit
+/// so that orchestrators don't restart healthy instances
+pub const PROBE_ROUTES: [&str; 2] = ["/healthz", "/readyz"];
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+	|config| {
+		config.service(healthz).service(readyz);
+	}
+}
+
+/// Liveness probe, which succeeds as long as the server is able to respond
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+	HttpResponse::Ok()
+		.insert_header((CACHE_CONTROL, HeaderValue::from_static("no-store")))
+		.json(HealthResponse { status: Status::Ok })
+}
+
+/// Readiness probe, which fails unless every configured maven repository is
+/// reachable
+#[get("/readyz")]
+async fn readyz(
+	state: CurrentApiData,
+	readiness: web::Data<Readiness>
+) -> impl Responder {
+	let upstreams = [
+		Some(("public", &state.public_maven_url)),
+		state
+			.internal_maven_url
+			.as_ref()
+			.map(|url| ("internal", url))
+	];
+
+	// Credentials are only sent to the url that artifacts are resolved from
+	let upstream_url = state.upstream_url();
+	let repository = readiness.probe_path.split('/').next().unwrap_or_default();
+	let auth = state.upstream_auth(repository);
+
+	let client = &state.client;
+	let timeout = readiness.timeout;
+	let upstreams = join_all(upstreams.into_iter().flatten().map(|(name, url)| {
+		let auth = auth.filter(|_| url == upstream_url);
+		let url = format!("{url}{path}", path = readiness.probe_path);
+		async move {
+			let start = Instant::now();
+			let result = maven::probe_maven_metadata(client, auth, &url, timeout).await;
+
+			let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+			match result {
+				Ok(()) => UpstreamStatus {
+					name,
+					url,
+					status: Status::Ok,
+					latency_ms,
+					error: None
+				},
+				Err(e) => UpstreamStatus {
+					name,
+					url,
+					status: Status::Unavailable,
+					latency_ms,
+					error: Some(match e.source() {
+						Some(source) => format!("{e}: {source}"),
+						None => e.to_string()
+					})
+				}
+			}
+		}
+	}))
+	.await;
+
+	let ready = upstreams
+		.iter()
+		.all(|upstream| matches!(upstream.status, Status::Ok));
+
+	if ready {
+		HttpResponse::Ok()
+	} else {
+		HttpResponse::ServiceUnavailable()
+	}
+	.insert_header((CACHE_CONTROL, HeaderValue::from_static("no-store")))
+	.json(ReadinessResponse {
+		status: if ready {
+			Status::Ok
+		} else {
+			Status::Unavailable
+		},
+		upstreams
+	})
+}
@@ -0,0 +1,574 @@
+use std::io::{self, Cursor, Read};
+
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use thiserror::Error;
+use tracing::debug;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::api::v1::responses::{
+	Checksum,
+	ChecksumType,
+	MetadataSource,
+	ModDependency,
+	ModMetadata,
+	NestedJar
+};
+
+#[derive(Error, Debug)]
+pub enum JarError {
+	#[error("The jar is not a valid zip archive: {0}")]
+	Zip(#[from] ZipError),
+	#[error("Unable to read a file in the jar: {0}")]
+	Io(#[from] io::Error),
+	#[error("Unable to parse {0}: {1}")]
+	Json(&'static str, serde_json::Error),
+	#[error("Unable to parse META-INF/mods.toml: {0}")]
+	Toml(#[from] toml::de::Error),
+	#[error("{0} is larger than the limit of {1} bytes")]
+	TooLarge(String, u64)
+}
+
+/// The most that is decompressed from a metadata file, so that a malformed or
+/// malicious jar can't use up the memory of the server
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// The most that is decompressed from a nested jar, see [`MAX_FILE_SIZE`]
+const MAX_NESTED_JAR_SIZE: u64 = 64 * 1024 * 1024;
+
+type Jar<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+/// What is read from an artifact jar
+#[derive(Clone)]
+pub struct JarContents {
+	pub metadata: Option<ModMetadata>,
+	pub nested: Vec<NestedJar>
+}
+
+/// Reads the mod metadata and nested jars of a jar, see [`read_metadata`] and
+/// [`read_nested_jars`]. Metadata which can't be read is left out, rather than
+/// failing the whole response that the jar is part of.
+pub fn read(jar: &[u8]) -> Result<JarContents, JarError> {
+	let mut jar = ZipArchive::new(Cursor::new(jar))?;
+
+	Ok(JarContents {
+		metadata: read_metadata(&mut jar).unwrap_or_else(|e| {
+			debug!("Unable to read the metadata of a jar: {e}");
+			None
+		}),
+		nested: read_nested_jars(&mut jar)?
+	})
+}
+
+/// Reads the metadata a jar declares about the mod in it, from the first of
+/// `fabric.mod.json`, `META-INF/mods.toml`, `mcmod.info` and the manifest that
+/// it contains. Returns [`None`] if it has none of them.
+fn read_metadata(jar: &mut Jar) -> Result<Option<ModMetadata>, JarError> {
+	let metadata = if let Some(json) = read_file(jar, "fabric.mod.json")? {
+		fabric_metadata(parse_json("fabric.mod.json", &json)?)
+	} else if let Some(toml) = read_file(jar, "META-INF/mods.toml")? {
+		forge_metadata(toml::from_str(&toml)?)
+	} else if let Some(json) = read_file(jar, "mcmod.info")? {
+		legacy_forge_metadata(parse_json("mcmod.info", &json)?)
+	} else if let Some(manifest) = read_file(jar, "META-INF/MANIFEST.MF")? {
+		manifest_metadata(&manifest)
+	} else {
+		return Ok(None);
+	};
+
+	Ok(Some(ModMetadata {
+		java_version: java_version(jar)?,
+		..metadata
+	}))
+}
+
+/// Lists the jars nested in a jar, which are declared by the `jars` of
+/// `fabric.mod.json` for Fabric, and by `META-INF/jarjar/metadata.json` for
+/// Forge. Each one is opened to read the ID of the mod in it. Declared jars
+/// which are missing are still listed, as that is usually what needs to be
+/// debugged, as are the ones too large to read.
+fn read_nested_jars(jar: &mut Jar) -> Result<Vec<NestedJar>, JarError> {
+	let mut paths = nested_paths(jar, "fabric.mod.json", "file");
+	paths.extend(nested_paths(jar, "META-INF/jarjar/metadata.json", "path"));
+
+	paths
+		.into_iter()
+		.map(|path| {
+			let mut nested = Vec::new();
+			match jar.by_name(&path) {
+				Ok(file) => file
+					.take(MAX_NESTED_JAR_SIZE + 1)
+					.read_to_end(&mut nested)?,
+				Err(ZipError::FileNotFound) =>
+					return Ok(NestedJar {
+						path,
+						checksum: None,
+						id: None
+					}),
+				Err(e) => return Err(e.into())
+			};
+			// Jars too large to read are still listed, just without a checksum
+			if nested.len() as u64 > MAX_NESTED_JAR_SIZE {
+				return Ok(NestedJar {
+					path,
+					checksum: None,
+					id: None
+				});
+			}
+
+			// Nested jars which can't be read are still listed, just without an ID
+			let id = ZipArchive::new(Cursor::new(nested.as_slice()))
+				.ok()
+				.and_then(|mut nested| read_metadata(&mut nested).ok().flatten())
+				.and_then(|metadata| metadata.id);
+
+			Ok(NestedJar {
+				checksum: Some(Checksum {
+					r#type: ChecksumType::Sha256,
+					hash: base16ct::lower::encode_string(&Sha256::digest(&nested))
+				}),
+				path,
+				id
+			})
+		})
+		.collect()
+}
+
+/// The paths listed as `key` of the entries of the `jars` list of a JSON file
+/// in the jar. Files which can't be read are treated as declaring none, like
+/// the metadata in [`read`].
+fn nested_paths(jar: &mut Jar, name: &'static str, key: &str) -> Vec<String> {
+	let Some(json) = read_file(jar, name)
+		.and_then(|json| json.map(|json| parse_json(name, &json)).transpose())
+		.unwrap_or_else(|e| {
+			debug!("Unable to read the nested jars of a jar: {e}");
+			None
+		})
+	else {
+		return Vec::new();
+	};
+
+	json.get("jars")
+		.and_then(Value::as_array)
+		.into_iter()
+		.flatten()
+		.filter_map(|entry| string(entry, key))
+		.collect()
+}
+
+/// Reads a file of the jar as text, or [`None`] if it doesn't exist
+fn read_file(jar: &mut Jar, name: &str) -> Result<Option<String>, JarError> {
+	let file = match jar.by_name(name) {
+		Ok(file) => file,
+		Err(ZipError::FileNotFound) => return Ok(None),
+		Err(e) => return Err(e.into())
+	};
+
+	let mut contents = String::new();
+	file.take(MAX_FILE_SIZE + 1).read_to_string(&mut contents)?;
+	if contents.len() as u64 > MAX_FILE_SIZE {
+		return Err(JarError::TooLarge(name.to_string(), MAX_FILE_SIZE));
+	}
+	// Some editors still prepend a byte order mark, which JSON parsers reject
+	Ok(Some(contents.trim_start_matches('\u{feff}').to_string()))
+}
+
+fn parse_json(name: &'static str, json: &str) -> Result<Value, JarError> {
+	serde_json::from_str(json).map_err(|e| JarError::Json(name, e))
+}
+
+fn string(value: &Value, key: &str) -> Option<String> {
+	value
+		.get(key)
+		.and_then(Value::as_str)
+		.filter(|s| !s.is_empty())
+		.map(str::to_string)
+}
+
+fn fabric_metadata(json: Value) -> ModMetadata {
+	let dependencies = [
+		("depends", true),
+		("recommends", false),
+		("suggests", false)
+	]
+	.into_iter()
+	.flat_map(|(key, required)| {
+		let dependencies = json.get(key).and_then(Value::as_object).cloned();
+		dependencies
+			.into_iter()
+			.flatten()
+			.map(move |(id, version)| {
+				ModDependency {
+					id,
+					// Either a single version range, or any of several ones
+					version: match version {
+						Value::String(version) => Some(version),
+						Value::Array(versions) => Some(
+							versions
+								.iter()
+								.filter_map(Value::as_str)
+								.collect::<Vec<_>>()
+								.join(" || ")
+						),
+						_ => None
+					},
+					required
+				}
+			})
+	})
+	.collect();
+
+	ModMetadata {
+		id: string(&json, "id"),
+		name: string(&json, "name"),
+		description: string(&json, "description"),
+		dependencies,
+		java_version: None,
+		source: MetadataSource::Fabric
+	}
+}
+
+fn forge_metadata(toml: toml::Table) -> ModMetadata {
+	let first_mod = toml
+		.get("mods")
+		.and_then(toml::Value::as_array)
+		.and_then(|mods| mods.first());
+	let field = |key: &str| {
+		first_mod
+			.and_then(|m| m.get(key))
+			.and_then(toml::Value::as_str)
+			.filter(|s| !s.is_empty())
+			.map(str::to_string)
+	};
+	let id = field("modId");
+
+	let dependencies = id
+		.as_ref()
+		.and_then(|id| toml.get("dependencies")?.get(id)?.as_array())
+		.into_iter()
+		.flatten()
+		.filter_map(|dependency| {
+			let string = |key| dependency.get(key).and_then(toml::Value::as_str);
+			Some(ModDependency {
+				id: string("modId")?.to_string(),
+				version: string("versionRange").map(str::to_string),
+				// Older versions use `mandatory`, newer ones `type`
+				required: match dependency.get("mandatory") {
+					Some(mandatory) => mandatory.as_bool().unwrap_or(true),
+					None => string("type").is_none_or(|t| t == "required")
+				}
+			})
+		})
+		.collect();
+
+	ModMetadata {
+		id,
+		name: field("displayName"),
+		description: field("description").map(|d| d.trim().to_string()),
+		dependencies,
+		java_version: None,
+		source: MetadataSource::ModsToml
+	}
+}
+
+fn legacy_forge_metadata(json: Value) -> ModMetadata {
+	// Either a list of mods, or an object holding it as `modList`
+	let first_mod = match &json {
+		Value::Array(mods) => mods.first(),
+		_ => json
+			.get("modList")
+			.and_then(Value::as_array)
+			.and_then(|mods| mods.first())
+	}
+	.cloned()
+	.unwrap_or_default();
+
+	let list = |key| {
+		first_mod
+			.get(key)
+			.and_then(Value::as_array)
+			.into_iter()
+			.flatten()
+			.filter_map(Value::as_str)
+			.map(str::to_string)
+			.collect::<Vec<_>>()
+	};
+	let required = list("requiredMods");
+	// Dependencies are written as ID@VERSION, where the version is optional
+	let dependency = |dependency: &str, required| {
+		let (id, version) = match dependency.split_once('@') {
+			Some((id, version)) => (id, Some(version.to_string())),
+			None => (dependency, None)
+		};
+		ModDependency {
+			id: id.to_string(),
+			version,
+			required
+		}
+	};
+
+	let mut dependencies = required
+		.iter()
+		.map(|d| dependency(d, true))
+		.collect::<Vec<_>>();
+	for optional in list("dependencies") {
+		let optional = dependency(&optional, false);
+		if !dependencies.iter().any(|d| d.id == optional.id) {
+			dependencies.push(optional);
+		}
+	}
+
+	ModMetadata {
+		id: string(&first_mod, "modid"),
+		name: string(&first_mod, "name"),
+		description: string(&first_mod, "description"),
+		dependencies,
+		java_version: None,
+		source: MetadataSource::McmodInfo
+	}
+}
+
+fn manifest_metadata(manifest: &str) -> ModMetadata {
+	// Long values are continued on lines starting with a space
+	let manifest = manifest.replace("\r\n", "\n").replace("\n ", "");
+	let attribute = |key: &str| {
+		manifest.lines().find_map(|line| {
+			let (name, value) = line.split_once(':')?;
+			(name.trim() == key && !value.trim().is_empty())
+				.then(|| value.trim().to_string())
+		})
+	};
+
+	ModMetadata {
+		id: attribute("Automatic-Module-Name"),
+		name: attribute("Implementation-Title")
+			.or_else(|| attribute("Specification-Title")),
+		description: None,
+		dependencies: Vec::new(),
+		java_version: None,
+		source: MetadataSource::Manifest
+	}
+}
+
+/// The Java version that the classes of the jar were compiled for, read from
+/// the header of its first class file
+fn java_version(jar: &mut Jar) -> Result<Option<u16>, JarError> {
+	// Multi-release jars hold classes for newer versions as well, which aren't the
+	// ones it requires
+	let Some(name) = jar
+		.file_names()
+		.find(|name| name.ends_with(".class") && !name.starts_with("META-INF/"))
+		.map(str::to_string)
+	else {
+		return Ok(None);
+	};
+
+	let mut header = [0; 8];
+	jar.by_name(&name)?.read_exact(&mut header)?;
+	if header[..4] != [0xca, 0xfe, 0xba, 0xbe] {
+		return Ok(None);
+	}
+
+	// Java 1.1 is major version 45, and every later version is one more
+	let major = u16::from_be_bytes([header[6], header[7]]);
+	Ok(major.checked_sub(44).filter(|version| *version > 0))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write as _;
+
+	use serde_json::json;
+	use zip::{write::SimpleFileOptions, ZipWriter};
+
+	use super::*;
+
+	fn dependencies(metadata: &ModMetadata) -> Vec<(&str, Option<&str>, bool)> {
+		metadata
+			.dependencies
+			.iter()
+			.map(|d| (d.id.as_str(), d.version.as_deref(), d.required))
+			.collect()
+	}
+
+	fn jar(files: &[(&str, &[u8])]) -> Vec<u8> {
+		let mut jar = ZipWriter::new(Cursor::new(Vec::new()));
+		for (name, contents) in files {
+			jar.start_file(*name, SimpleFileOptions::default()).unwrap();
+			jar.write_all(contents).unwrap();
+		}
+		jar.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn fabric_metadata_reads_dependencies_by_kind() {
+		let metadata = fabric_metadata(json!({
+			"id": "oneconfig",
+			"name": "OneConfig",
+			"description": "",
+			"depends": { "fabricloader": ">=0.15", "minecraft": ["1.20.3", "1.20.4"] },
+			"recommends": { "modmenu": "*" },
+			"suggests": { "sodium": 5 }
+		}));
+
+		assert_eq!(metadata.id.as_deref(), Some("oneconfig"));
+		assert_eq!(metadata.name.as_deref(), Some("OneConfig"));
+		assert_eq!(metadata.description, None);
+		assert!(matches!(metadata.source, MetadataSource::Fabric));
+		assert_eq!(dependencies(&metadata), [
+			("fabricloader", Some(">=0.15"), true),
+			("minecraft", Some("1.20.3 || 1.20.4"), true),
+			("modmenu", Some("*"), false),
+			("sodium", None, false)
+		]);
+	}
+
+	#[test]
+	fn forge_metadata_reads_the_first_mod() {
+		let metadata = forge_metadata(
+			toml::from_str(
+				r#"
+				[[mods]]
+				modId = "oneconfig"
+				displayName = "OneConfig"
+				description = """
+				A config library
+				"""
+
+				[[mods]]
+				modId = "other"
+
+				[[dependencies.oneconfig]]
+				modId = "forge"
+				mandatory = true
+				versionRange = "[49,)"
+
+				[[dependencies.oneconfig]]
+				modId = "jei"
+				type = "optional"
+
+				[[dependencies.oneconfig]]
+				modId = "minecraft"
+
+				[[dependencies.other]]
+				modId = "unrelated"
+				"#
+			)
+			.unwrap()
+		);
+
+		assert_eq!(metadata.id.as_deref(), Some("oneconfig"));
+		assert_eq!(metadata.name.as_deref(), Some("OneConfig"));
+		assert_eq!(metadata.description.as_deref(), Some("A config library"));
+		assert!(matches!(metadata.source, MetadataSource::ModsToml));
+		assert_eq!(dependencies(&metadata), [
+			("forge", Some("[49,)"), true),
+			("jei", None, false),
+			("minecraft", None, true)
+		]);
+	}
+
+	#[test]
+	fn legacy_forge_metadata_reads_lists_and_mod_lists() {
+		let list = json!([{
+			"modid": "oneconfig",
+			"name": "OneConfig",
+			"requiredMods": ["Forge@[11.15,)"],
+			"dependencies": ["Forge", "essential"]
+		}]);
+		let metadata = legacy_forge_metadata(list.clone());
+
+		assert_eq!(metadata.id.as_deref(), Some("oneconfig"));
+		assert_eq!(metadata.name.as_deref(), Some("OneConfig"));
+		assert!(matches!(metadata.source, MetadataSource::McmodInfo));
+		assert_eq!(dependencies(&metadata), [
+			("Forge", Some("[11.15,)"), true),
+			("essential", None, false)
+		]);
+
+		let metadata =
+			legacy_forge_metadata(json!({ "modListVersion": 2, "modList": list }));
+		assert_eq!(metadata.id.as_deref(), Some("oneconfig"));
+
+		let metadata = legacy_forge_metadata(json!({}));
+		assert_eq!(metadata.id, None);
+		assert!(metadata.dependencies.is_empty());
+	}
+
+	#[test]
+	fn manifest_metadata_joins_continued_lines() {
+		let metadata = manifest_metadata(
+			"Manifest-Version: 1.0\r\nAutomatic-Module-Name: org.polyfrost.one\r\n \
+			 config\r\nSpecification-Title: OneConfig\r\nImplementation-Title: \r\n"
+		);
+
+		assert_eq!(metadata.id.as_deref(), Some("org.polyfrost.oneconfig"));
+		assert_eq!(metadata.name.as_deref(), Some("OneConfig"));
+		assert!(matches!(metadata.source, MetadataSource::Manifest));
+	}
+
+	#[test]
+	fn read_lists_nested_jars() {
+		let nested = jar(&[("fabric.mod.json", br#"{ "id": "polyui" }"#)]);
+		let jar = jar(&[
+			(
+				"fabric.mod.json",
+				br#"{
+					"id": "oneconfig",
+					"jars": [{ "file": "META-INF/jars/polyui.jar" }, { "file": "missing.jar" }]
+				}"#
+			),
+			("META-INF/jars/polyui.jar", &nested)
+		]);
+		let contents = read(&jar).unwrap();
+
+		assert_eq!(contents.metadata.unwrap().id.as_deref(), Some("oneconfig"));
+		let [polyui, missing] = contents.nested.as_slice() else {
+			panic!("expected two nested jars");
+		};
+		assert_eq!(polyui.path, "META-INF/jars/polyui.jar");
+		assert_eq!(polyui.id.as_deref(), Some("polyui"));
+		assert_eq!(
+			polyui
+				.checksum
+				.as_ref()
+				.map(|checksum| checksum.hash.as_str()),
+			Some(base16ct::lower::encode_string(&Sha256::digest(&nested)).as_str())
+		);
+		assert_eq!(missing.path, "missing.jar");
+		assert!(missing.checksum.is_none() && missing.id.is_none());
+	}
+
+	#[test]
+	fn read_lists_large_nested_jars_without_checksum() {
+		let large = vec![0; MAX_NESTED_JAR_SIZE as usize + 1];
+		let jar = jar(&[
+			(
+				"META-INF/jarjar/metadata.json",
+				br#"{ "jars": [{ "path": "META-INF/jarjar/large.jar" }] }"#
+			),
+			("META-INF/jarjar/large.jar", &large)
+		]);
+		let contents = read(&jar).unwrap();
+
+		let [large] = contents.nested.as_slice() else {
+			panic!("expected one nested jar");
+		};
+		assert_eq!(large.path, "META-INF/jarjar/large.jar");
+		assert!(large.checksum.is_none() && large.id.is_none());
+	}
+
+	#[test]
+	fn read_leaves_out_metadata_which_cant_be_read() {
+		let large = vec![b' '; MAX_FILE_SIZE as usize + 1];
+		for jar in [
+			jar(&[("mcmod.info", &large)]),
+			jar(&[("fabric.mod.json", br#"{ "id": "#)]),
+			jar(&[("META-INF/mods.toml", b"[[mods]")]),
+			jar(&[("META-INF/MANIFEST.MF", b"Automatic-Module-Name: \xff")])
+		] {
+			let contents = read(&jar).unwrap();
+			assert!(contents.metadata.is_none());
+			assert!(contents.nested.is_empty());
+		}
+	}
+}
@@ -0,0 +1,24 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// A lock is only poisoned when a thread panics while holding it. The locks of
+// the server are never held across anything that can panic halfway through
+// updating what they guard, so a poisoned lock still guards consistent data and
+// is simply used as if it weren't poisoned.
+
+/// Locks a mutex, ignoring poisoning
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Locks a read-write lock for reading, ignoring poisoning
+pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+	lock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Locks a read-write lock for writing, ignoring poisoning
+pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+	lock.write()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -1,15 +1,35 @@
 #![feature(duration_constructors_lite, let_chains)]
 
+mod access_log;
 mod api;
+mod client_ip;
+mod compatibility;
+mod config;
+mod health;
+mod jar;
+mod lock;
 mod maven;
+mod metrics;
+mod minimum_version;
+mod rate_limit;
+mod rollout;
+mod statistics;
+mod telemetry;
 mod types;
+mod upstream_auth;
 
-use std::{net::Ipv4Addr, time::Duration};
+use std::{net::Ipv4Addr, path::PathBuf, time::Duration};
 
-use actix_web::{web, App, HttpServer};
-use api::v1::{ApiData, CacheKey, CacheValue, ETagType};
+use access_log::AccessLog;
+use actix_web::{http::header::HeaderValue, web, App, HttpServer};
+use api::v1::SharedApiData;
 use clap::Parser;
-use moka::future::Cache;
+use client_ip::TrustedProxies;
+use ipnet::IpNet;
+use rate_limit::{RateLimit, RateLimiter};
+use statistics::Statistics;
+use tracing_actix_web::TracingLogger;
+use upstream_auth::CredentialSource;
 use url::Url;
 
 /// The main command that starts the backend HTTP server. The server can be
@@ -24,6 +44,61 @@ struct AppCommand {
 	/// The host address for the HTTP server to listen on
 	#[clap(long, env = "BACKEND_LISTEN_HOST", default_value_t = Ipv4Addr::new(0, 0, 0, 0))]
 	pub host: Ipv4Addr,
+	/// If set, Prometheus metrics will be served at /metrics on this port,
+	/// which is kept separate from the API so that it doesn't need to be
+	/// exposed publicly
+	#[clap(long, env = "BACKEND_METRICS_PORT")]
+	pub metrics_port: Option<u16>,
+	/// If set, traces of requests and their upstream maven requests will be
+	/// exported to this OTLP/HTTP traces endpoint, such as
+	/// http://localhost:4318/v1/traces
+	#[clap(long, env = "BACKEND_OTLP_ENDPOINT")]
+	pub otlp_endpoint: Option<Url>,
+	/// The service name that exported traces are reported under
+	#[clap(long, env = "BACKEND_OTLP_SERVICE_NAME", default_value = env!("CARGO_PKG_NAME"))]
+	pub otlp_service_name: String,
+	/// Comma separated addresses or CIDR ranges of the reverse proxies in front
+	/// of the server, which are trusted to report client addresses through the
+	/// X-Forwarded-For header
+	#[clap(
+		long,
+		env = "BACKEND_TRUSTED_PROXIES",
+		value_delimiter = ',',
+		value_parser = client_ip::parse_trusted_proxy
+	)]
+	pub trusted_proxies: Vec<IpNet>,
+	/// If set, the token bucket limit of requests each client can make to each
+	/// route, written as BURST:RATE where RATE is the tokens refilled per
+	/// second
+	#[clap(long, env = "BACKEND_RATE_LIMIT")]
+	pub rate_limit: Option<RateLimit>,
+	/// Comma separated limits of specific routes, written as PATTERN=BURST:RATE
+	/// where PATTERN is the route such as /v1/artifacts/oneconfig. These
+	/// override --rate-limit.
+	#[clap(
+		long,
+		env = "BACKEND_ROUTE_RATE_LIMITS",
+		value_delimiter = ',',
+		value_parser = rate_limit::parse_route_rate_limit
+	)]
+	pub route_rate_limits: Vec<(String, RateLimit)>,
+	/// The tokens taken from the rate limit of a client by a response served
+	/// from the internal cache, between 0 (exempt) and 1 (same as any other
+	/// request)
+	#[clap(long, env = "BACKEND_RATE_LIMIT_CACHE_HIT_COST", default_value_t = 0.0)]
+	pub rate_limit_cache_hit_cost: f64,
+	/// The path of a maven-metadata.xml file, relative to the maven root urls,
+	/// which is fetched from each of them to check readiness at /readyz
+	#[clap(
+		long,
+		env = "BACKEND_READINESS_PROBE_PATH",
+		default_value = "releases/org/polyfrost/oneconfig/1.8.9-forge/maven-metadata.xml"
+	)]
+	pub readiness_probe_path: String,
+	/// How long, in milliseconds, each maven repository has to respond to the
+	/// readiness check before it is considered unavailable
+	#[clap(long, env = "BACKEND_READINESS_TIMEOUT", default_value_t = 2000)]
+	pub readiness_timeout: u64,
 	/// If passed, the server will be downgraded to HTTP/1.1 rather than HTTP/2
 	#[clap(long, env = "BACKEND_USE_HTTP1", default_value_t = false)]
 	pub http1: bool,
@@ -37,53 +112,200 @@ struct AppCommand {
 	/// same host as this backend, then this can be set to a local IP to
 	/// greatly speed up requests.
 	#[clap(long, env = "BACKEND_INTERNAL_MAVEN_URL")]
-	pub internal_maven_url: Option<Url>
+	pub internal_maven_url: Option<Url>,
+	/// If set, the url this API is publicly served at. Artifact urls of the
+	/// OneConfig and dependency groups returned by the API then point at its
+	/// /v1/download endpoint, which counts the download before redirecting to
+	/// the public maven url.
+	#[clap(long, env = "BACKEND_PUBLIC_API_URL")]
+	pub public_api_url: Option<Url>,
+	/// Comma separated credentials to send to the maven repository when
+	/// resolving artifacts, written as REPOSITORY=env:VAR or
+	/// REPOSITORY=file:PATH, where the variable or file holds `basic
+	/// USER:PASSWORD` or `bearer TOKEN`. A repository of * applies to every
+	/// repository without its own credentials. They are only sent to the
+	/// internal maven url if there is one.
+	#[clap(
+		long,
+		env = "BACKEND_UPSTREAM_AUTH",
+		value_delimiter = ',',
+		value_parser = upstream_auth::parse_repository_auth
+	)]
+	pub upstream_auth: Vec<(String, CredentialSource)>,
+	/// The Cache-Control header value sent with API responses. They can all
+	/// change over time, as even pinned versions may be revoked later.
+	#[clap(
+		long,
+		env = "BACKEND_CACHE_CONTROL",
+		default_value = "public, max-age=60, stale-while-revalidate=300"
+	)]
+	pub cache_control: HeaderValue,
+	/// How long, in seconds, API responses are kept in the internal cache
+	#[clap(long, env = "BACKEND_CACHE_TTL", default_value_t = 120)]
+	pub cache_ttl: u64,
+	/// The maximum size, in bytes, of the internal cache of API responses
+	#[clap(long, env = "BACKEND_CACHE_CAPACITY", default_value_t = 10 * 1024 * 1024)]
+	pub cache_capacity: u64,
+	/// Comma separated artifacts of the OneConfig group which are shared by
+	/// every Minecraft version and mod loader, and can be resolved at
+	/// /v1/artifacts/{artifact}
+	#[clap(
+		long,
+		env = "BACKEND_ARTIFACTS",
+		value_delimiter = ',',
+		default_value = "stage1,relaunch"
+	)]
+	pub artifacts: Vec<String>,
+	/// Comma separated groups of the artifacts bundled with OneConfig, which
+	/// can be downloaded through /v1/download along with the OneConfig group
+	#[clap(
+		long,
+		env = "BACKEND_DEPENDENCY_GROUPS",
+		value_delimiter = ',',
+		default_value = "org.polyfrost"
+	)]
+	pub dependency_groups: Vec<String>,
+	/// If set, a directory of release notes written as ARTIFACT/VERSION.md,
+	/// which are served at /v1/artifacts/{artifact}/changelog instead of the
+	/// changelog published to maven
+	#[clap(long, env = "BACKEND_CHANGELOG_DIRECTORY")]
+	pub changelog_directory: Option<PathBuf>,
+	/// If set, the SQLite database that update checks and downloads are counted
+	/// in, which is created if it doesn't exist. The counts are served at
+	/// /v1/statistics.
+	#[clap(long, env = "BACKEND_STATISTICS_DATABASE")]
+	pub statistics_database: Option<PathBuf>,
+	/// If set, a TOML file whose settings override the maven urls, cache
+	/// settings, artifacts, dependency groups and changelog directory. It is
+	/// read again whenever the server receives SIGHUP, so they can be changed
+	/// without a restart.
+	#[clap(long, env = "BACKEND_CONFIG")]
+	pub config: Option<PathBuf>,
+	/// How long, in seconds, in-flight requests are given to finish once the
+	/// server receives SIGTERM
+	#[clap(long, env = "BACKEND_SHUTDOWN_TIMEOUT", default_value_t = 30)]
+	pub shutdown_timeout: u64
 }
 
 #[tokio::main]
 #[allow(clippy::needless_return)] // Clippy seems to be hallucinating a return statement at the end of main()
 async fn main() {
-	env_logger::init();
-
 	let args = AppCommand::parse();
+	let tracer_provider =
+		telemetry::init(args.otlp_endpoint.as_ref(), &args.otlp_service_name)
+			.expect("Unable to set up the OTLP exporter");
 	let listen_args = (args.host, args.port);
-	let data = web::Data::new(ApiData {
-		internal_maven_url: args.internal_maven_url.map(|url| url.to_string()),
-		public_maven_url: args.public_maven_url.to_string(),
-		client: reqwest::ClientBuilder::new()
-			.user_agent(concat!(
-				env!("CARGO_PKG_NAME"),
-				"/",
-				env!("CARGO_PKG_VERSION"),
-				" (",
-				env!("CARGO_PKG_REPOSITORY"),
-				")"
-			))
-			.build()
-			.unwrap()
-			.into(),
-		cache: Cache::builder()
-			.time_to_live(Duration::from_mins(2))
-			.weigher(|k: &CacheKey, v: &CacheValue| {
-				(k.path.len()
-					+ k.query.len() + const { std::mem::size_of::<ETagType>() }
-					+ v.response.len()
-					+ std::mem::size_of_val(&v.headers))
-				.try_into()
-				.unwrap_or(u32::MAX)
-			})
-			.max_capacity(/* 10 MiB */ const { 10 * 1024 * 1024 })
-			.build()
+	let metrics_listen_args = args.metrics_port.map(|port| (args.host, port));
+	let settings =
+		config::Settings::load(&args).expect("Unable to load the configuration");
+	let client = reqwest::ClientBuilder::new()
+		.user_agent(concat!(
+			env!("CARGO_PKG_NAME"),
+			"/",
+			env!("CARGO_PKG_VERSION"),
+			" (",
+			env!("CARGO_PKG_REPOSITORY"),
+			")"
+		))
+		.build()
+		.unwrap();
+	let data = web::Data::new(SharedApiData::new(settings.api_data(client.into(), None)));
+
+	#[cfg(unix)]
+	tokio::spawn(config::reload_on_sighup(
+		args.clone(),
+		settings,
+		data.clone()
+	));
+
+	let readiness = web::Data::new(health::Readiness {
+		probe_path: args.readiness_probe_path,
+		timeout: Duration::from_millis(args.readiness_timeout)
+	});
+	let trusted_proxies = web::Data::new(TrustedProxies(args.trusted_proxies));
+	// Held until the server has stopped, so that the last lines are still written
+	let (access_log, _access_log_guard) = AccessLog::stdout();
+	let access_log = web::Data::new(access_log);
+	let statistics = args.statistics_database.as_deref().map(|path| {
+		web::Data::new(
+			Statistics::open(path).expect("Unable to open the statistics database")
+		)
+	});
+	// Kept to close the statistics once the server has stopped
+	let statistics_writer = statistics.clone();
+	let rate_limiter = web::Data::new(RateLimiter::new(
+		args.rate_limit,
+		args.route_rate_limits,
+		args.rate_limit_cache_hit_cost
+	));
+
+	let metrics_server = metrics_listen_args.map(|listen_args| {
+		let handle = web::Data::new(
+			metrics::install().expect("Unable to install the metrics recorder")
+		);
+		let data = data.clone();
+
+		HttpServer::new(move || {
+			App::new()
+				.app_data(data.clone())
+				.app_data(handle.clone())
+				.service(metrics::render_metrics)
+		})
+		.workers(1)
+		.shutdown_timeout(args.shutdown_timeout)
+		.bind(listen_args)
+		.expect("Unable to bind metrics server on specified IP and port")
+		.run()
 	});
 
-	HttpServer::new(move || {
+	let server = HttpServer::new(move || {
 		App::new()
+			.wrap(actix_web::middleware::from_fn(
+				rate_limit::rate_limit_middleware
+			))
+			// Wraps the rate limit, so that rate limited requests are counted as well
+			.wrap(actix_web::middleware::from_fn(
+				metrics::http_metrics_middleware
+			))
+			.wrap(actix_web::middleware::from_fn(
+				access_log::access_log_middleware
+			))
+			.wrap(TracingLogger::default())
 			.app_data(data.clone())
+			.app_data(trusted_proxies.clone())
+			.app_data(access_log.clone())
+			.app_data(readiness.clone())
+			.app_data(rate_limiter.clone())
+			.configure(|config| {
+				// Without a database, handlers see no statistics and skip recording
+				if let Some(statistics) = &statistics {
+					config.app_data(statistics.clone());
+				}
+			})
+			.configure(health::configure())
 			.configure(api::v1::configure())
 	})
+	.shutdown_timeout(args.shutdown_timeout)
 	.bind_auto_h2c(listen_args)
 	.expect("Unable to bind on specified IP and port")
-	.run()
-	.await
+	.run();
+
+	tokio::try_join!(server, async {
+		match metrics_server {
+			Some(metrics_server) => metrics_server.await,
+			None => Ok(())
+		}
+	})
 	.expect("Unable to start HTTP server");
+
+	// Events recorded by the last requests are still queued for the writer
+	if let Some(statistics) = statistics_writer {
+		statistics.close();
+	}
+
+	if let Some(tracer_provider) = tracer_provider
+		&& let Err(e) = tracer_provider.shutdown()
+	{
+		tracing::error!("Unable to flush pending traces: {e}");
+	}
 }
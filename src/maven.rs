@@ -1,22 +1,38 @@
-use actix_web::web;
-use reqwest::Client;
+use std::{
+	io,
+	sync::Arc,
+	time::{Duration, Instant}
+};
+
+use actix_web::web::{self, Bytes, BytesMut};
+use metrics::{counter, histogram};
+use moka::future::Cache;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use semver::Version;
 use thiserror::Error;
+use tracing::instrument;
 
 use crate::{
 	api::v1::ApiData,
+	jar::{self, JarContents, JarError},
+	metrics::{UPSTREAM_REQUESTS, UPSTREAM_REQUEST_DURATION},
+	rollout::RolloutCohort,
 	types::{
 		gradle_module_metadata::{Dependency, GradleModuleMetadata},
-		maven_metadata::MavenMetadata
-	}
+		maven_metadata::MavenMetadata,
+		oneconfig::Channel
+	},
+	upstream_auth::UpstreamAuth
 };
 
 #[derive(Error, Debug)]
 pub enum MavenError {
 	#[error("A response/request error from reqwest")]
-	Reqwest(#[from] reqwest::Error),
+	Reqwest(#[source] Arc<reqwest::Error>),
 	#[error("An error occurred while trying to parse an XML response")]
 	XmlParse(#[from] quick_xml::DeError),
+	#[error("An error occurred while trying to parse a JSON response")]
+	JsonParse(#[from] serde_json::Error),
 	#[error("An error occurred while trying to parse versions with SemVer")]
 	Semver(#[from] semver::Error),
 	#[error("There were no artifact versions in the maven-metadata.xml file")]
@@ -25,13 +41,163 @@ pub enum MavenError {
 		"There was a mismatch between the requested module ID or group and the response"
 	)]
 	ArtifactMismatch,
-	#[error("Unable to find any artifacts")]
-	NoArtifacts
+	#[error("The maven-metadata.xml file for the artifact could not be found")]
+	MetadataNotFound,
+	#[error("The Gradle module metadata for the artifact version could not be found")]
+	ModuleNotFound,
+	#[error("The checksum for the artifact could not be found")]
+	ChecksumNotFound,
+	#[error("The file of the artifact could not be found")]
+	ArtifactNotFound,
+	#[error("The jar of the artifact could not be read: {0}")]
+	Jar(#[from] JarError),
+	#[error("The file is larger than the limit of {0} bytes")]
+	FileTooLarge(u64)
+}
+
+impl From<reqwest::Error> for MavenError {
+	fn from(value: reqwest::Error) -> Self { Self::Reqwest(Arc::new(value)) }
+}
+
+/// Why a file could not be fetched, which is cloned for every fetch waiting
+/// for the same file
+#[derive(Clone)]
+enum FetchError {
+	Reqwest(Arc<reqwest::Error>),
+	TooLarge(u64)
+}
+
+impl From<reqwest::Error> for FetchError {
+	fn from(value: reqwest::Error) -> Self { Self::Reqwest(Arc::new(value)) }
+}
+
+impl From<FetchError> for MavenError {
+	fn from(value: FetchError) -> Self {
+		match value {
+			FetchError::Reqwest(e) => Self::Reqwest(e),
+			FetchError::TooLarge(max_size) => Self::FileTooLarge(max_size)
+		}
+	}
+}
+
+/// The most that is downloaded of a metadata file, checksum or changelog
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// The most that is downloaded of a jar to read what is in it
+const MAX_JAR_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Files fetched from the maven repository by URL, or [`None`] for the ones
+/// which don't exist. These are shared by the queries of a batch, which are
+/// likely to need the same files.
+pub type UpstreamLookups = Cache<String, Option<Bytes>>;
+
+/// Sends a request to maven, recording its latency and status under the given
+/// operation
+async fn send(
+	request: RequestBuilder,
+	operation: &'static str
+) -> Result<Response, reqwest::Error> {
+	let start = Instant::now();
+	let response = request.send().await;
+	let labels = [
+		("operation", operation.to_string()),
+		("status", match &response {
+			Ok(response) => response.status().as_str().to_string(),
+			Err(_) => "error".to_string()
+		})
+	];
+
+	counter!(UPSTREAM_REQUESTS, &labels).increment(1);
+	histogram!(UPSTREAM_REQUEST_DURATION, &labels).record(start.elapsed());
+
+	response
+}
+
+/// Builds a GET request to the maven repository, along with its credentials.
+/// The URL must be on the internal maven url if there is one, as credentials
+/// are never sent to the public one otherwise.
+fn get(state: &ApiData, repository: &str, url: &str) -> RequestBuilder {
+	with_auth(state.client.get(url), state.upstream_auth(repository))
 }
 
 #[inline]
-pub fn get_dep_url(url: &str, repository: &str, dep: &Dependency) -> String {
-	let filename = match dep
+fn with_auth(request: RequestBuilder, auth: Option<&UpstreamAuth>) -> RequestBuilder {
+	match auth {
+		Some(auth) => auth.apply(request),
+		None => request
+	}
+}
+
+/// Maps a 404 response to the given error, and any other unsuccessful status to
+/// a reqwest error
+#[inline]
+fn check_status(
+	response: Response,
+	not_found: MavenError
+) -> Result<Response, MavenError> {
+	if response.status() == StatusCode::NOT_FOUND {
+		return Err(not_found);
+	}
+
+	Ok(response.error_for_status()?)
+}
+
+/// Fetches a file from the maven repository, or [`None`] if it doesn't exist.
+/// Files larger than `max_size` are not downloaded. When lookups are shared,
+/// each file is only fetched once, and concurrent fetches of the same file
+/// wait for a single request. Failed requests are not kept, but every fetch
+/// waiting for one fails with its error.
+async fn fetch_file(
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
+	repository: &str,
+	url: &str,
+	operation: &'static str,
+	max_size: u64
+) -> Result<Option<Bytes>, MavenError> {
+	let file = fetch_unshared_file(state, repository, url, operation, max_size);
+	match lookups {
+		Some(lookups) => lookups
+			.try_get_with(url.to_string(), file)
+			.await
+			.map_err(|e| e.as_ref().clone().into()),
+		None => Ok(file.await?)
+	}
+}
+
+async fn fetch_unshared_file(
+	state: &ApiData,
+	repository: &str,
+	url: &str,
+	operation: &'static str,
+	max_size: u64
+) -> Result<Option<Bytes>, FetchError> {
+	let response = send(get(state, repository, url), operation).await?;
+	if response.status() == StatusCode::NOT_FOUND {
+		return Ok(None);
+	}
+
+	let mut response = response.error_for_status()?;
+	// The length can only be checked up front if the repository sends it
+	if response
+		.content_length()
+		.is_some_and(|length| length > max_size)
+	{
+		return Err(FetchError::TooLarge(max_size));
+	}
+	let mut file = BytesMut::new();
+	while let Some(chunk) = response.chunk().await? {
+		if (file.len() + chunk.len()) as u64 > max_size {
+			return Err(FetchError::TooLarge(max_size));
+		}
+		file.extend_from_slice(&chunk);
+	}
+
+	Ok(Some(file.freeze()))
+}
+
+#[inline]
+pub fn get_dep_filename(dep: &Dependency) -> String {
+	match dep
 		.third_party_compatibility
 		.as_ref()
 		.map(|i| &i.artifact_selector)
@@ -48,38 +214,58 @@ pub fn get_dep_url(url: &str, repository: &str, dep: &Dependency) -> String {
 			artifact = dep.module,
 			version = dep.version.requires
 		)
-	};
+	}
+}
+
+#[inline]
+pub fn get_dep_url(url: &str, repository: &str, dep: &Dependency) -> String {
 	format!(
-		"{url}{repository}/{group}/{artifact}/{version}/{filename}",
-		group = dep.group.replace('.', "/"),
-		artifact = dep.module,
-		version = dep.version.requires
+		"{url}{path}",
+		path = get_artifact_path(
+			repository,
+			&dep.group,
+			&dep.module,
+			&dep.version.requires,
+			&get_dep_filename(dep)
+		)
 	)
 }
 
+/// The path of an artifact file relative to the maven root url
+#[inline]
+pub fn get_artifact_path(
+	repository: &str,
+	group: &str,
+	artifact: &str,
+	version: &str,
+	file: &str
+) -> String {
+	format!(
+		"{repository}/{group}/{artifact}/{version}/{file}",
+		group = group.replace('.', "/")
+	)
+}
+
+// A missing artifact is an expected result when probing for variants, so errors
+// are only logged at debug level
+#[instrument(skip(state, lookups), err(level = "debug"))]
 pub async fn fetch_maven_metadata(
-	state: &web::Data<ApiData>,
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
 	repository: &str,
 	group: &str,
 	artifact: &str
 ) -> Result<MavenMetadata, MavenError> {
-	let xml = state
-		.client
-		.get(format!(
-			"{url}{repository}/{group}/{artifact}/maven-metadata.xml",
-			url = state
-				.internal_maven_url
-				.clone()
-				.unwrap_or(state.public_maven_url.clone()),
-			group = group.replace('.', "/")
-		))
-		.send()
+	let url = format!(
+		"{url}{repository}/{group}/{artifact}/maven-metadata.xml",
+		url = state.upstream_url(),
+		group = group.replace('.', "/")
+	);
+	let xml = fetch_file(state, lookups, repository, &url, "metadata", MAX_FILE_SIZE)
 		.await?
-		.error_for_status()?
-		.text()
-		.await?;
+		.ok_or(MavenError::MetadataNotFound)?;
 
-	let result: MavenMetadata = quick_xml::de::from_str(&xml)?;
+	let result: MavenMetadata = quick_xml::de::from_str(&String::from_utf8_lossy(&xml))?;
 
 	if group != result.group_id || artifact != result.artifact_id {
 		return Err(MavenError::ArtifactMismatch);
@@ -88,13 +274,18 @@ pub async fn fetch_maven_metadata(
 	Ok(result)
 }
 
+#[instrument(skip(state, lookups, cohort), err(level = "debug"))]
 pub async fn fetch_latest_artifact(
-	state: &web::Data<ApiData>,
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
 	repository: &str,
 	group: &str,
-	artifact: &str
+	artifact: &str,
+	channel: Option<Channel>,
+	cohort: &RolloutCohort
 ) -> Result<Version, MavenError> {
-	let metadata = fetch_maven_metadata(state, repository, group, artifact).await?;
+	let metadata =
+		fetch_maven_metadata(state, lookups, repository, group, artifact).await?;
 	metadata
 		.versioning
 		.versions
@@ -111,41 +302,127 @@ pub async fn fetch_latest_artifact(
 					|| !component.chars().any(|c| c.is_ascii_digit())
 			})
 		})
+		.filter(|v| channel.is_none_or(|channel| Channel::of(v) <= channel))
+		.filter(|v| !cohort.withholds(&state.rollouts, group, artifact, v, channel))
+		.filter(|v| state.revocation(group, artifact, &v.to_string()).is_none())
 		.max()
 		.ok_or(MavenError::NoVersions)
 }
 
+#[instrument(skip(state, lookups), err)]
 pub async fn fetch_module_metadata(
-	state: &web::Data<ApiData>,
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
 	repository: &str,
 	group: &str,
 	artifact: &str,
 	version: &str
 ) -> Result<GradleModuleMetadata, MavenError> {
-	Ok(state
-		.client
-		.get(format!(
-			"{url}{repository}/{group}/{artifact}/{version}/{artifact}-{version}.module",
-			url = state
-				.internal_maven_url
-				.clone()
-				.unwrap_or(state.public_maven_url.clone()),
-			group = group.replace('.', "/")
-		))
-		.send()
+	let url = format!(
+		"{url}{repository}/{group}/{artifact}/{version}/{artifact}-{version}.module",
+		url = state.upstream_url(),
+		group = group.replace('.', "/")
+	);
+	let json = fetch_file(state, lookups, repository, &url, "module", MAX_FILE_SIZE)
 		.await?
-		.error_for_status()?
-		.json()
-		.await?)
+		.ok_or(MavenError::ModuleNotFound)?;
+
+	Ok(serde_json::from_slice(&json)?)
 }
 
-#[inline]
-pub async fn fetch_checksum(client: &Client, url: &str) -> Result<String, MavenError> {
-	Ok(client
-		.get(format!("{url}.sha256"))
-		.send()
+#[instrument(skip(state, lookups), err)]
+pub async fn fetch_checksum(
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
+	repository: &str,
+	url: &str
+) -> Result<String, MavenError> {
+	let checksum = fetch_file(
+		state,
+		lookups,
+		repository,
+		&format!("{url}.sha256"),
+		"checksum",
+		MAX_FILE_SIZE
+	)
+	.await?
+	.ok_or(MavenError::ChecksumNotFound)?;
+
+	Ok(String::from_utf8_lossy(&checksum).into_owned())
+}
+
+/// Opens the jar of an artifact and reads the metadata of the mod and the jars
+/// nested in it, see [`jar::read`]. Jars are identified by their checksum, so
+/// each one is only downloaded and read once.
+#[instrument(skip(state, lookups), err)]
+pub async fn fetch_jar_contents(
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
+	repository: &str,
+	url: &str,
+	checksum: &str
+) -> Result<JarContents, MavenError> {
+	let checksum = checksum.trim().to_ascii_lowercase();
+	if let Some(contents) = state.jar_contents.get(&checksum).await {
+		return Ok(contents);
+	}
+
+	let jar = fetch_file(state, lookups, repository, url, "artifact", MAX_JAR_SIZE)
 		.await?
-		.error_for_status()?
+		.ok_or(MavenError::ArtifactNotFound)?;
+	// Decompressing and hashing jars takes a while, so it is kept off of the
+	// worker threads
+	let contents = web::block(move || jar::read(&jar))
+		.await
+		.map_err(|e| JarError::Io(io::Error::other(e)))??;
+
+	state.jar_contents.insert(checksum, contents.clone()).await;
+	Ok(contents)
+}
+
+/// Fetches the release notes published next to an artifact version as its
+/// `changelog` classifier, or [`None`] if the version has none
+#[instrument(skip(state, lookups), err)]
+pub async fn fetch_changelog(
+	state: &ApiData,
+	lookups: Option<&UpstreamLookups>,
+	repository: &str,
+	group: &str,
+	artifact: &str,
+	version: &str
+) -> Result<Option<String>, MavenError> {
+	let url = format!(
+		"{url}{path}",
+		url = state.upstream_url(),
+		path = get_artifact_path(
+			repository,
+			group,
+			artifact,
+			version,
+			&format!("{artifact}-{version}-changelog.md")
+		)
+	);
+	let notes =
+		fetch_file(state, lookups, repository, &url, "changelog", MAX_FILE_SIZE).await?;
+
+	Ok(notes.map(|notes| String::from_utf8_lossy(&notes).into_owned()))
+}
+
+/// Checks that a maven-metadata.xml file can be fetched and parsed within the
+/// timeout, used to tell whether a maven repository is reachable
+#[instrument(skip(client, auth), err)]
+pub async fn probe_maven_metadata(
+	client: &Client,
+	auth: Option<&UpstreamAuth>,
+	url: &str,
+	timeout: Duration
+) -> Result<(), MavenError> {
+	let response =
+		send(with_auth(client.get(url).timeout(timeout), auth), "probe").await?;
+	let xml = check_status(response, MavenError::MetadataNotFound)?
 		.text()
-		.await?)
+		.await?;
+
+	quick_xml::de::from_str::<MavenMetadata>(&xml)?;
+	Ok(())
 }
@@ -0,0 +1,139 @@
+use std::time::Instant;
+
+use actix_web::{
+	body::MessageBody,
+	dev::{ServiceRequest, ServiceResponse},
+	get,
+	middleware::Next,
+	web,
+	HttpResponse,
+	Responder
+};
+use metrics::{
+	counter,
+	describe_counter,
+	describe_gauge,
+	describe_histogram,
+	gauge,
+	histogram,
+	Unit
+};
+use metrics_exporter_prometheus::{
+	BuildError,
+	Matcher,
+	PrometheusBuilder,
+	PrometheusHandle
+};
+
+use crate::api::v1::CurrentApiData;
+
+pub const HTTP_REQUESTS: &str = "backend_http_requests_total";
+pub const HTTP_REQUEST_DURATION: &str = "backend_http_request_duration_seconds";
+pub const CACHE_LOOKUPS: &str = "backend_cache_lookups_total";
+pub const CACHE_WEIGHTED_SIZE: &str = "backend_cache_weighted_size_bytes";
+pub const CACHE_ENTRIES: &str = "backend_cache_entries";
+pub const UPSTREAM_REQUESTS: &str = "backend_upstream_requests_total";
+pub const UPSTREAM_REQUEST_DURATION: &str = "backend_upstream_request_duration_seconds";
+pub const RESOLVED_DEPENDENCIES: &str = "backend_resolved_dependencies";
+pub const RATE_LIMITED_REQUESTS: &str = "backend_rate_limited_requests_total";
+pub const DOWNLOADS: &str = "backend_downloads_total";
+
+/// Installs the global Prometheus recorder, after which all metrics recorded
+/// by the backend can be rendered with the returned handle. Until then, all
+/// metrics are discarded.
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+	let handle = PrometheusBuilder::new()
+		.set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &[
+			0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0
+		])?
+		.set_buckets_for_metric(Matcher::Full(RESOLVED_DEPENDENCIES.to_string()), &[
+			0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0
+		])?
+		.install_recorder()?;
+
+	describe_counter!(HTTP_REQUESTS, "The number of HTTP requests handled");
+	describe_histogram!(
+		HTTP_REQUEST_DURATION,
+		Unit::Seconds,
+		"The time taken to handle HTTP requests"
+	);
+	describe_counter!(
+		CACHE_LOOKUPS,
+		"The number of response cache lookups, by whether they were a hit or a miss"
+	);
+	describe_gauge!(
+		CACHE_WEIGHTED_SIZE,
+		Unit::Bytes,
+		"The approximate size of all cached responses"
+	);
+	describe_gauge!(CACHE_ENTRIES, "The number of cached responses");
+	describe_counter!(UPSTREAM_REQUESTS, "The number of requests sent to maven");
+	describe_histogram!(
+		UPSTREAM_REQUEST_DURATION,
+		Unit::Seconds,
+		"The time taken for maven to respond to requests"
+	);
+	describe_histogram!(
+		RESOLVED_DEPENDENCIES,
+		"The number of dependencies resolved for each artifact response"
+	);
+	describe_counter!(
+		RATE_LIMITED_REQUESTS,
+		"The number of requests rejected because the client exceeded its rate limit"
+	);
+	describe_counter!(
+		DOWNLOADS,
+		"The number of artifact downloads redirected to maven, by artifact version"
+	);
+
+	Ok(handle)
+}
+
+/// Records the count and duration of every request, labelled by the matched
+/// route pattern rather than the path to keep the number of series bounded
+pub async fn http_metrics_middleware(
+	service_request: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	let start = Instant::now();
+	let method = service_request.method().to_string();
+
+	let response = next.call(service_request).await;
+	let (route, status) = match &response {
+		Ok(response) => (
+			response
+				.request()
+				.match_pattern()
+				.unwrap_or_else(|| "unmatched".to_string()),
+			response.status()
+		),
+		Err(e) => ("unknown".to_string(), e.as_response_error().status_code())
+	};
+	let labels = [
+		("method", method),
+		("route", route),
+		("status", status.as_str().to_string())
+	];
+
+	counter!(HTTP_REQUESTS, &labels).increment(1);
+	histogram!(HTTP_REQUEST_DURATION, &labels).record(start.elapsed());
+
+	response
+}
+
+/// Renders all metrics in the Prometheus text format
+#[get("/metrics")]
+pub async fn render_metrics(
+	handle: web::Data<PrometheusHandle>,
+	state: CurrentApiData
+) -> impl Responder {
+	// The cache size is only calculated after pending tasks are run
+	state.cache.run_pending_tasks().await;
+	gauge!(CACHE_WEIGHTED_SIZE).set(state.cache.weighted_size() as f64);
+	gauge!(CACHE_ENTRIES).set(state.cache.entry_count() as f64);
+
+	handle.run_upkeep();
+	HttpResponse::Ok()
+		.content_type("text/plain; version=0.0.4")
+		.body(handle.render())
+}
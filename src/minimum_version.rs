@@ -0,0 +1,51 @@
+use semver::Version;
+use serde::Deserialize;
+
+use crate::types::oneconfig::{oneconfig_group, ModLoader};
+
+/// The lowest version of an artifact that clients may keep using, configured in
+/// the config file. Update checks from older versions are told that updating
+/// is required, such as for critical security fixes.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MinimumVersion {
+	/// The group of the artifact, which is the OneConfig group if unset
+	#[serde(default = "oneconfig_group")]
+	pub group: String,
+	/// The artifact the minimum applies to, or every artifact of the group if
+	/// unset
+	pub artifact: Option<String>,
+	pub version: Version,
+	/// If set, the minimum only applies to update checks for this Minecraft
+	/// version
+	pub minecraft_version: Option<String>,
+	/// If set, the minimum only applies to update checks for this mod loader
+	pub loader: Option<ModLoader>
+}
+
+impl MinimumVersion {
+	/// Whether the installed version of an artifact is below this minimum. The
+	/// target is the Minecraft version and mod loader being updated, if known,
+	/// as minimums scoped to either of them only apply to matching targets.
+	pub fn requires_update(
+		&self,
+		group: &str,
+		artifact: &str,
+		installed: &Version,
+		target: Option<(&str, &ModLoader)>
+	) -> bool {
+		let in_scope = match target {
+			Some((minecraft_version, loader)) =>
+				self.minecraft_version
+					.as_deref()
+					.is_none_or(|v| v == minecraft_version)
+					&& self.loader.as_ref().is_none_or(|l| l == loader),
+			None => self.minecraft_version.is_none() && self.loader.is_none()
+		};
+
+		in_scope
+			&& self.group == group
+			&& self.artifact.as_deref().is_none_or(|a| a == artifact)
+			&& *installed < self.version
+	}
+}
@@ -0,0 +1,242 @@
+use std::{
+	collections::HashMap,
+	net::{IpAddr, Ipv6Addr},
+	str::FromStr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant}
+};
+
+use actix_web::{
+	body::{EitherBody, MessageBody},
+	dev::{ServiceRequest, ServiceResponse},
+	http::header::{HeaderValue, RETRY_AFTER},
+	middleware::Next,
+	web,
+	HttpResponse
+};
+use metrics::counter;
+use moka::future::Cache;
+
+use crate::{
+	api::v1::{
+		responses::{consts::*, ErrorResponse},
+		CacheResult
+	},
+	client_ip::TrustedProxies,
+	health,
+	lock,
+	metrics::RATE_LIMITED_REQUESTS
+};
+
+/// The most buckets that are kept at once. Past that, the least recently used
+/// ones are dropped, which only ever lets their clients make more requests.
+const MAX_BUCKETS: u64 = 100_000;
+
+/// A token bucket limit, written as `BURST:RATE`. Each client can make up to
+/// `BURST` requests at once, after which tokens refill at `RATE` per second.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+	burst: f64,
+	per_second: f64
+}
+
+impl FromStr for RateLimit {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let (burst, per_second) = value
+			.split_once(':')
+			.ok_or_else(|| format!("expected BURST:RATE, got {value}"))?;
+		let limit = Self {
+			burst: burst
+				.parse()
+				.map_err(|e| format!("invalid burst {burst}: {e}"))?,
+			per_second: per_second
+				.parse()
+				.map_err(|e| format!("invalid rate {per_second}: {e}"))?
+		};
+
+		if !(limit.burst >= 1.0 && limit.per_second > 0.0) {
+			return Err(format!(
+				"the burst must be at least 1 and the rate above 0, got {value}"
+			));
+		}
+
+		Ok(limit)
+	}
+}
+
+/// Parses the limit of a single route, written as `PATTERN=BURST:RATE` where
+/// the pattern is the route as it is matched, such as
+/// `/v1/artifacts/{artifact}`
+pub fn parse_route_rate_limit(value: &str) -> Result<(String, RateLimit), String> {
+	let (route, limit) = value
+		.rsplit_once('=')
+		.ok_or_else(|| format!("expected PATTERN=BURST:RATE, got {value}"))?;
+
+	Ok((route.to_string(), limit.parse()?))
+}
+
+struct Bucket {
+	tokens: f64,
+	updated: Instant
+}
+
+impl Bucket {
+	/// Refills the bucket for the time passed since it was last updated
+	fn refill(&mut self, limit: RateLimit) {
+		let now = Instant::now();
+		self.tokens = (self.tokens
+			+ now.duration_since(self.updated).as_secs_f64() * limit.per_second)
+			.min(limit.burst);
+		self.updated = now;
+	}
+}
+
+/// Limits how many requests each client can make to each route, so that a
+/// single client can't flood the upstream maven repository through cache
+/// misses
+pub struct RateLimiter {
+	default: Option<RateLimit>,
+	routes: HashMap<String, RateLimit>,
+	/// The tokens taken by a response served from the cache, rather than the
+	/// single token taken by any other response
+	cache_hit_cost: f64,
+	buckets: Cache<(String, IpAddr), Arc<Mutex<Bucket>>>
+}
+
+impl RateLimiter {
+	pub fn new(
+		default: Option<RateLimit>,
+		routes: Vec<(String, RateLimit)>,
+		cache_hit_cost: f64
+	) -> Self {
+		// Buckets which are idle for long enough to be full again can be dropped, as
+		// they are the same as a new bucket
+		let refill_time = default
+			.iter()
+			.chain(routes.iter().map(|(_, limit)| limit))
+			.map(|limit| limit.burst / limit.per_second)
+			.fold(60.0, f64::max);
+
+		Self {
+			default,
+			routes: routes.into_iter().collect(),
+			cache_hit_cost: cache_hit_cost.clamp(0.0, 1.0),
+			buckets: Cache::builder()
+				.max_capacity(MAX_BUCKETS)
+				.time_to_idle(Duration::from_secs_f64(refill_time))
+				.build()
+		}
+	}
+
+	/// The limit of a route, which is the default one unless the route has its
+	/// own, or is a health probe, see [`health::PROBE_ROUTES`]
+	fn limit(&self, route: &str) -> Option<RateLimit> {
+		match self.routes.get(route) {
+			Some(limit) => Some(*limit),
+			None if health::PROBE_ROUTES.contains(&route) => None,
+			None => self.default
+		}
+	}
+}
+
+/// The address whose bucket a client takes tokens from. IPv6 clients are
+/// usually given a whole /64 to pick addresses from, so they share a bucket
+/// with the rest of it.
+fn bucket_address(client_ip: IpAddr) -> IpAddr {
+	match client_ip.to_canonical() {
+		IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !0 << 64)),
+		ip => ip
+	}
+}
+
+/// Takes a token from the bucket of the client for the requested route before
+/// handling the request, responding with a problem instead if there are none
+/// left. Responses served from the cache give back the difference to their
+/// cost afterwards.
+pub async fn rate_limit_middleware(
+	service_request: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+	let route = service_request
+		.match_pattern()
+		.unwrap_or_else(|| "unmatched".to_string());
+	let limited = service_request
+		.app_data::<web::Data<RateLimiter>>()
+		.and_then(|rate_limiter| {
+			let limit = rate_limiter.limit(&route)?;
+			let client_ip = service_request
+				.app_data::<web::Data<TrustedProxies>>()?
+				.client_ip(service_request.request())?;
+
+			Some((rate_limiter.clone(), limit, client_ip))
+		});
+
+	// Routes without a limit, and requests without a known client, are let through
+	let Some((rate_limiter, limit, client_ip)) = limited else {
+		return next
+			.call(service_request)
+			.await
+			.map(|res| res.map_into_left_body());
+	};
+
+	let bucket = rate_limiter
+		.buckets
+		.get_with((route.clone(), bucket_address(client_ip)), async {
+			Arc::new(Mutex::new(Bucket {
+				tokens: limit.burst,
+				updated: Instant::now()
+			}))
+		})
+		.await;
+
+	let taken = {
+		let mut bucket = lock::lock(&bucket);
+		bucket.refill(limit);
+
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(Duration::from_secs_f64(
+				(1.0 - bucket.tokens) / limit.per_second
+			))
+		}
+	};
+
+	if let Err(retry_after) = taken {
+		counter!(RATE_LIMITED_REQUESTS, "route" => route.clone()).increment(1);
+
+		let mut response: HttpResponse = ErrorResponse::RateLimited {
+			title: RATE_LIMITED_TITLE.to_string(),
+			detail: format!("Too many requests to {route}, try again later"),
+			instance: format!(
+				"{RATE_LIMITED_INSTANCE_PREFIX}?{query}",
+				query = service_request.query_string()
+			)
+		}
+		.into();
+		response.headers_mut().insert(
+			RETRY_AFTER,
+			HeaderValue::from(retry_after.as_secs_f64().ceil() as u64)
+		);
+
+		return Ok(service_request
+			.into_response(response)
+			.map_into_right_body());
+	}
+
+	let response = next.call(service_request).await?;
+
+	if matches!(
+		response.response().extensions().get::<CacheResult>(),
+		Some(CacheResult::Hit | CacheResult::NotModified)
+	) {
+		let mut bucket = lock::lock(&bucket);
+		bucket.tokens =
+			(bucket.tokens + 1.0 - rate_limiter.cache_hit_cost).min(limit.burst);
+	}
+
+	Ok(response.map_into_left_body())
+}
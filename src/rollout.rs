@@ -0,0 +1,271 @@
+use std::{
+	future::{ready, Ready},
+	sync::Arc
+};
+
+use actix_web::{
+	dev::Payload,
+	error::ErrorInternalServerError,
+	http::header::HeaderName,
+	FromRequest,
+	HttpMessage as _,
+	HttpRequest
+};
+use semver::Version;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest as _, Sha256};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+	api::v1::{ApiData, CurrentApiData},
+	types::oneconfig::{oneconfig_group, Channel}
+};
+
+/// The header holding a stable identifier of the client, which decides whether
+/// it is part of a rollout
+pub const CLIENT_ID: HeaderName = HeaderName::from_static("x-client-id");
+
+/// Gradually releases a version of an artifact to a percentage of clients,
+/// configured in the config file. Until a client is selected, the version is
+/// skipped when resolving the latest version, so it stays on the previous one.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Rollout {
+	/// The group of the artifact, which is the OneConfig group if unset
+	#[serde(default = "oneconfig_group")]
+	pub group: String,
+	/// The artifact being rolled out, or every artifact of the group if unset,
+	/// such as every Minecraft version and mod loader of OneConfig
+	pub artifact: Option<String>,
+	pub version: Version,
+	/// The percentage of clients the version is released to, from 0 to 100
+	pub percentage: f64,
+	/// When the rollout starts, before which no client is selected
+	#[serde(deserialize_with = "deserialize_datetime")]
+	pub start: OffsetDateTime,
+	/// If set, the least stable channel the rollout applies to. Clients of less
+	/// stable channels receive the version right away.
+	pub channel: Option<Channel>
+}
+
+/// Reads a TOML offset date-time, such as `2025-06-01T12:00:00Z`
+fn deserialize_datetime<'de, D: Deserializer<'de>>(
+	deserializer: D
+) -> Result<OffsetDateTime, D::Error> {
+	let datetime = toml::value::Datetime::deserialize(deserializer)?;
+	OffsetDateTime::parse(&datetime.to_string(), &Rfc3339).map_err(|_| {
+		serde::de::Error::custom(format!(
+			"expected a date-time with an offset, such as 2025-06-01T12:00:00Z, got \
+			 {datetime}"
+		))
+	})
+}
+
+impl Rollout {
+	/// Whether the rollout restricts the given version of an artifact when it
+	/// is resolved for the given channel
+	fn applies(
+		&self,
+		group: &str,
+		artifact: &str,
+		version: &Version,
+		channel: Option<Channel>
+	) -> bool {
+		self.group == group
+			&& self.artifact.as_deref().is_none_or(|a| a == artifact)
+			&& self.version == *version
+			&& match (self.channel, channel) {
+				(Some(rollout), Some(requested)) => requested <= rollout,
+				_ => true
+			}
+	}
+
+	/// Whether the client is selected for the rollout at the given time.
+	/// Clients are hashed into buckets along with the version, so that the
+	/// same clients aren't always the first to receive a new version.
+	fn admits(&self, client_id: Option<&str>, now: OffsetDateTime) -> bool {
+		if now < self.start {
+			return false;
+		}
+		if self.percentage >= 100.0 {
+			return true;
+		}
+		let Some(client_id) = client_id else {
+			return false;
+		};
+
+		let hash = Sha256::new()
+			.chain_update(self.version.to_string())
+			.chain_update([0])
+			.chain_update(client_id)
+			.finalize();
+		let bucket =
+			u64::from_be_bytes(hash[..8].try_into().unwrap_or_default()) % 10_000;
+
+		(bucket as f64) < self.percentage * 100.0
+	}
+}
+
+/// Which rollouts of the current [`ApiData`] the client of a request is
+/// selected for. It is part of the cache key, as clients in different cohorts
+/// may resolve different versions.
+#[derive(Clone, Hash, PartialEq, Eq, Default)]
+pub struct RolloutCohort(Arc<[bool]>);
+
+impl RolloutCohort {
+	pub fn get(req: &HttpRequest, state: &ApiData) -> Self {
+		if let Some(cohort) = req.extensions().get::<Self>() {
+			return cohort.clone();
+		}
+
+		let client_id = req
+			.headers()
+			.get(CLIENT_ID)
+			.and_then(|value| value.to_str().ok());
+		let now = OffsetDateTime::now_utc();
+		let cohort = Self(
+			state
+				.rollouts
+				.iter()
+				.map(|rollout| rollout.admits(client_id, now))
+				.collect()
+		);
+
+		req.extensions_mut().insert(cohort.clone());
+		cohort
+	}
+
+	/// Whether the version of an artifact is withheld from the client by a
+	/// rollout it isn't selected for yet
+	pub fn withholds(
+		&self,
+		rollouts: &[Rollout],
+		group: &str,
+		artifact: &str,
+		version: &Version,
+		channel: Option<Channel>
+	) -> bool {
+		rollouts.iter().enumerate().any(|(i, rollout)| {
+			rollout.applies(group, artifact, version, channel)
+				&& !self.0.get(i).copied().unwrap_or_default()
+		})
+	}
+}
+
+impl FromRequest for RolloutCohort {
+	type Error = actix_web::Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+		ready(
+			CurrentApiData::get(req)
+				.map(|state| Self::get(req, &state))
+				.ok_or_else(|| ErrorInternalServerError("ApiData is not configured"))
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::Duration;
+
+	use super::*;
+	use crate::types::oneconfig::ONECONFIG_GROUP;
+
+	fn rollout(percentage: f64, channel: Option<Channel>) -> Rollout {
+		Rollout {
+			group: oneconfig_group(),
+			artifact: Some("1.8.9-forge".to_string()),
+			version: Version::new(1, 0, 0),
+			percentage,
+			start: OffsetDateTime::UNIX_EPOCH,
+			channel
+		}
+	}
+
+	fn after_start() -> OffsetDateTime { OffsetDateTime::UNIX_EPOCH + Duration::days(1) }
+
+	#[test]
+	fn no_client_is_admitted_at_zero_percent() {
+		let rollout = rollout(0.0, None);
+		assert!((0..1000).all(|i| !rollout.admits(Some(&i.to_string()), after_start())));
+	}
+
+	#[test]
+	fn every_client_is_admitted_at_hundred_percent() {
+		let rollout = rollout(100.0, None);
+		assert!((0..1000).all(|i| rollout.admits(Some(&i.to_string()), after_start())));
+		assert!(rollout.admits(None, after_start()));
+	}
+
+	#[test]
+	fn no_client_is_admitted_before_start() {
+		let rollout = rollout(100.0, None);
+		let before = OffsetDateTime::UNIX_EPOCH - Duration::seconds(1);
+		assert!(!rollout.admits(Some("client"), before));
+		assert!(!rollout.admits(None, before));
+	}
+
+	#[test]
+	fn clients_without_id_are_only_admitted_at_hundred_percent() {
+		assert!(!rollout(99.9, None).admits(None, after_start()));
+	}
+
+	#[test]
+	fn clients_are_admitted_in_proportion_to_percentage() {
+		let rollout = rollout(25.0, None);
+		let admitted = (0..10_000)
+			.filter(|i| rollout.admits(Some(&format!("client-{i}")), after_start()))
+			.count();
+		assert!(
+			(2_250..=2_750).contains(&admitted),
+			"{admitted} clients admitted"
+		);
+	}
+
+	#[test]
+	fn admitted_clients_stay_admitted_as_percentage_grows() {
+		let (smaller, larger) = (rollout(10.0, None), rollout(50.0, None));
+		assert!((0..1000).map(|i| i.to_string()).all(|client| {
+			!smaller.admits(Some(&client), after_start())
+				|| larger.admits(Some(&client), after_start())
+		}));
+	}
+
+	#[test]
+	fn applies_to_matching_artifact_and_version_only() {
+		let rollout = rollout(50.0, None);
+		let version = Version::new(1, 0, 0);
+		let group = ONECONFIG_GROUP;
+		assert!(rollout.applies(group, "1.8.9-forge", &version, None));
+		assert!(!rollout.applies(group, "1.20.4-fabric", &version, None));
+		assert!(!rollout.applies(group, "1.8.9-forge", &Version::new(1, 0, 1), None));
+		assert!(!rollout.applies("org.polyfrost", "1.8.9-forge", &version, None));
+	}
+
+	#[test]
+	fn applies_to_channels_at_least_as_stable_as_its_own() {
+		let rollout = rollout(50.0, Some(Channel::Beta));
+		let version = Version::new(1, 0, 0);
+		let applies =
+			|channel| rollout.applies(ONECONFIG_GROUP, "1.8.9-forge", &version, channel);
+		assert!(applies(Some(Channel::Stable)));
+		assert!(applies(Some(Channel::Beta)));
+		assert!(!applies(Some(Channel::Alpha)));
+		assert!(!applies(Some(Channel::Snapshot)));
+		assert!(applies(None));
+	}
+
+	#[test]
+	fn withholds_versions_of_rollouts_the_client_is_not_admitted_to() {
+		let rollouts = [rollout(50.0, None)];
+		let version = Version::new(1, 0, 0);
+		let withholds = |cohort: &RolloutCohort| {
+			cohort.withholds(&rollouts, ONECONFIG_GROUP, "1.8.9-forge", &version, None)
+		};
+		assert!(withholds(&RolloutCohort(Arc::from([false]))));
+		assert!(!withholds(&RolloutCohort(Arc::from([true]))));
+		// Cohorts from before a reload may have fewer rollouts than the state
+		assert!(withholds(&RolloutCohort::default()));
+	}
+}
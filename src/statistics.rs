@@ -0,0 +1,306 @@
+use std::{
+	collections::BTreeMap,
+	path::Path,
+	sync::{mpsc, Mutex},
+	thread::{self, JoinHandle},
+	time::Duration
+};
+
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use thiserror::Error;
+use time::{Date, OffsetDateTime};
+
+use crate::{api::v1::responses::StatisticsCount, lock};
+
+#[derive(Error, Debug)]
+pub enum StatisticsError {
+	#[error("An error occurred in the statistics database: {0}")]
+	Sqlite(#[from] rusqlite::Error),
+	#[error("Unable to start the statistics writer: {0}")]
+	Spawn(#[from] std::io::Error)
+}
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS update_checks (
+		day TEXT NOT NULL,
+		minecraft_version TEXT NOT NULL,
+		loader TEXT NOT NULL,
+		repository TEXT NOT NULL,
+		oneconfig_version TEXT NOT NULL,
+		count INTEGER NOT NULL,
+		PRIMARY KEY (day, minecraft_version, loader, repository, oneconfig_version)
+	) WITHOUT ROWID;
+
+	CREATE TABLE IF NOT EXISTS downloads (
+		day TEXT NOT NULL,
+		group_id TEXT NOT NULL,
+		artifact TEXT NOT NULL,
+		version TEXT NOT NULL,
+		count INTEGER NOT NULL,
+		PRIMARY KEY (day, group_id, artifact, version)
+	) WITHOUT ROWID;
+";
+
+/// An update check answered with a OneConfig version, inserted into the
+/// extensions of the response by the handler so that it is recorded even when
+/// the response is served from the cache
+#[derive(Clone)]
+pub struct UpdateCheck {
+	pub minecraft_version: String,
+	pub loader: String,
+	pub repository: String,
+	pub oneconfig_version: String
+}
+
+/// A download of an artifact file through the download endpoint
+pub struct Download {
+	pub group: String,
+	pub artifact: String,
+	pub version: String
+}
+
+enum Event {
+	UpdateCheck(UpdateCheck),
+	Download(Download)
+}
+
+/// A field that counts of a table can be grouped by
+pub trait Field: Copy + Send + 'static {
+	const TABLE: &'static str;
+	const ALL: &'static [Self];
+
+	/// The column of the field in the table
+	fn column(self) -> &'static str;
+
+	/// The name of the field in responses and queries
+	fn name(self) -> &'static str;
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateCheckField {
+	Day,
+	MinecraftVersion,
+	Loader,
+	Repository,
+	#[serde(rename = "oneconfig_version")]
+	OneConfigVersion
+}
+
+impl Field for UpdateCheckField {
+	const ALL: &'static [Self] = &[
+		Self::Day,
+		Self::MinecraftVersion,
+		Self::Loader,
+		Self::Repository,
+		Self::OneConfigVersion
+	];
+	const TABLE: &'static str = "update_checks";
+
+	fn column(self) -> &'static str { self.name() }
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Day => "day",
+			Self::MinecraftVersion => "minecraft_version",
+			Self::Loader => "loader",
+			Self::Repository => "repository",
+			Self::OneConfigVersion => "oneconfig_version"
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadField {
+	Day,
+	Group,
+	Artifact,
+	Version
+}
+
+impl Field for DownloadField {
+	const ALL: &'static [Self] = &[Self::Day, Self::Group, Self::Artifact, Self::Version];
+	const TABLE: &'static str = "downloads";
+
+	fn column(self) -> &'static str {
+		match self {
+			// GROUP is a keyword in SQL
+			Self::Group => "group_id",
+			_ => self.name()
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Day => "day",
+			Self::Group => "group",
+			Self::Artifact => "artifact",
+			Self::Version => "version"
+		}
+	}
+}
+
+/// Aggregate statistics of update checks and downloads, counted per day in an
+/// SQLite database. Events are written in batches by a separate thread, so
+/// recording them never blocks a request.
+pub struct Statistics {
+	/// Taken once the server has stopped, which closes the channel
+	events: Mutex<Option<mpsc::Sender<(Date, Event)>>>,
+	writer: Mutex<Option<JoinHandle<()>>>,
+	reader: Mutex<Connection>
+}
+
+impl Statistics {
+	pub fn open(path: &Path) -> Result<Self, StatisticsError> {
+		let mut writer = open_connection(path)?;
+		writer.execute_batch(SCHEMA)?;
+		let reader = open_connection(path)?;
+
+		let (events, receiver) = mpsc::channel();
+		let writer = thread::Builder::new()
+			.name("statistics".to_string())
+			.spawn(move || {
+				while let Ok(event) = receiver.recv() {
+					// Whatever else was recorded in the meantime is written in the same
+					// transaction
+					let batch = [event].into_iter().chain(receiver.try_iter().take(1023));
+					if let Err(e) = write(&mut writer, batch) {
+						tracing::error!("Unable to write statistics: {e}");
+					}
+				}
+			})?;
+
+		Ok(Self {
+			events: Mutex::new(Some(events)),
+			writer: Mutex::new(Some(writer)),
+			reader: Mutex::new(reader)
+		})
+	}
+
+	/// Stops recording events, and waits for the ones already recorded to be
+	/// written. This blocks until they are, so it is only meant to be called
+	/// once the server has stopped.
+	pub fn close(&self) {
+		drop(lock::lock(&self.events).take());
+		if let Some(writer) = lock::lock(&self.writer).take()
+			&& writer.join().is_err()
+		{
+			tracing::error!("The statistics writer panicked");
+		}
+	}
+
+	pub fn record_update_check(&self, update_check: UpdateCheck) {
+		self.record(Event::UpdateCheck(update_check));
+	}
+
+	pub fn record_download(&self, download: Download) {
+		self.record(Event::Download(download));
+	}
+
+	fn record(&self, event: Event) {
+		let events = lock::lock(&self.events);
+		let sent = events.as_ref().is_some_and(|events| {
+			events
+				.send((OffsetDateTime::now_utc().date(), event))
+				.is_ok()
+		});
+		if !sent {
+			tracing::warn!("The statistics writer has stopped, dropping an event");
+		}
+	}
+
+	/// Sums the counts of a table between two days, both inclusive, grouped by
+	/// the given fields and sorted from most to least common. This blocks on
+	/// the database, so it should be run with [`actix_web::web::block`].
+	pub fn counts<F: Field>(
+		&self,
+		fields: &[F],
+		from: Option<Date>,
+		to: Option<Date>
+	) -> Result<Vec<StatisticsCount>, StatisticsError> {
+		let columns = fields
+			.iter()
+			.map(|field| field.column())
+			.collect::<Vec<_>>()
+			.join(", ");
+		let (select, group_by) = if fields.is_empty() {
+			(String::new(), String::new())
+		} else {
+			(format!("{columns}, "), format!("GROUP BY {columns}"))
+		};
+		let sql = format!(
+			"SELECT {select}SUM(count) FROM {table} WHERE (?1 IS NULL OR day >= ?1) AND \
+			 (?2 IS NULL OR day <= ?2) {group_by} ORDER BY SUM(count) DESC",
+			table = F::TABLE
+		);
+
+		let reader = lock::lock(&self.reader);
+		let mut statement = reader.prepare_cached(&sql)?;
+		let rows = statement.query_map(
+			params![from.map(|d| d.to_string()), to.map(|d| d.to_string())],
+			|row| {
+				let values = fields
+					.iter()
+					.enumerate()
+					.map(|(i, field)| Ok((field.name().to_string(), row.get(i)?)))
+					.collect::<rusqlite::Result<BTreeMap<_, _>>>()?;
+
+				Ok(StatisticsCount {
+					fields: values,
+					// SUM is NULL when there are no rows at all
+					count: row.get::<_, Option<i64>>(fields.len())?.unwrap_or(0) as u64
+				})
+			}
+		)?;
+
+		Ok(rows.collect::<Result<_, _>>()?)
+	}
+}
+
+fn open_connection(path: &Path) -> Result<Connection, rusqlite::Error> {
+	let connection = Connection::open(path)?;
+	// Let the writer and reader use the database at the same time
+	connection.pragma_update(None, "journal_mode", "WAL")?;
+	connection.pragma_update(None, "synchronous", "NORMAL")?;
+	connection.busy_timeout(Duration::from_secs(5))?;
+	Ok(connection)
+}
+
+fn write(
+	connection: &mut Connection,
+	batch: impl Iterator<Item = (Date, Event)>
+) -> Result<(), rusqlite::Error> {
+	let transaction = connection.transaction()?;
+	{
+		let mut update_checks = transaction.prepare_cached(
+			"INSERT INTO update_checks VALUES (?1, ?2, ?3, ?4, ?5, 1) ON CONFLICT DO \
+			 UPDATE SET count = count + 1"
+		)?;
+		let mut downloads = transaction.prepare_cached(
+			"INSERT INTO downloads VALUES (?1, ?2, ?3, ?4, 1) ON CONFLICT DO UPDATE SET \
+			 count = count + 1"
+		)?;
+
+		for (day, event) in batch {
+			let day = day.to_string();
+			match event {
+				Event::UpdateCheck(check) => update_checks.execute(params![
+					day,
+					check.minecraft_version,
+					check.loader,
+					check.repository,
+					check.oneconfig_version
+				])?,
+				Event::Download(download) => downloads.execute(params![
+					day,
+					download.group,
+					download.artifact,
+					download.version
+				])?
+			};
+		}
+	}
+	transaction.commit()
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig as _};
+use opentelemetry_sdk::{
+	propagation::TraceContextPropagator,
+	trace::SdkTracerProvider,
+	Resource
+};
+use tracing_subscriber::{
+	filter::LevelFilter,
+	layer::SubscriberExt as _,
+	util::SubscriberInitExt as _,
+	EnvFilter,
+	Layer as _
+};
+use url::Url;
+
+/// Installs the global tracing subscriber. Logs are written to stderr and
+/// filtered with RUST_LOG, while spans are exported to the OTLP endpoint if one
+/// is given.
+///
+/// The returned tracer provider should be shut down before exiting, so that any
+/// spans which haven't been exported yet are flushed.
+pub fn init(
+	otlp_endpoint: Option<&Url>,
+	service_name: &str
+) -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+	let provider = otlp_endpoint
+		.map(|endpoint| {
+			let exporter = SpanExporter::builder()
+				.with_http()
+				.with_endpoint(endpoint.as_str())
+				.build()?;
+
+			Ok::<_, ExporterBuildError>(
+				SdkTracerProvider::builder()
+					.with_batch_exporter(exporter)
+					.with_resource(
+						Resource::builder()
+							.with_service_name(service_name.to_string())
+							.build()
+					)
+					.build()
+			)
+		})
+		.transpose()?;
+
+	// Spans are exported regardless of RUST_LOG, which only controls what is
+	// logged to stderr
+	let otlp_layer = provider.as_ref().map(|provider| {
+		tracing_opentelemetry::layer()
+			.with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+			.with_filter(LevelFilter::INFO)
+	});
+
+	tracing_subscriber::registry()
+		.with(
+			tracing_subscriber::fmt::layer()
+				.with_writer(io::stderr)
+				.with_filter(EnvFilter::from_default_env())
+		)
+		.with(otlp_layer)
+		.init();
+
+	// Continue traces started by clients or proxies through the traceparent header
+	global::set_text_map_propagator(TraceContextPropagator::new());
+
+	Ok(provider)
+}
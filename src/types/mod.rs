@@ -1,2 +1,3 @@
 pub mod gradle_module_metadata;
 pub mod maven_metadata;
+pub mod oneconfig;
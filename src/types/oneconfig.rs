@@ -0,0 +1,112 @@
+use std::fmt::Display;
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const ONECONFIG_GROUP: &str = "org.polyfrost.oneconfig";
+
+/// The default group of artifacts in the config file
+pub fn oneconfig_group() -> String { ONECONFIG_GROUP.to_string() }
+
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ModLoader {
+	Forge,
+	Fabric,
+	NeoForge,
+	Quilt
+}
+
+impl Display for ModLoader {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Fabric => "fabric",
+			Self::Forge => "forge",
+			Self::NeoForge => "neoforge",
+			Self::Quilt => "quilt"
+		})
+	}
+}
+
+/// How stable an artifact version is, from the most to the least stable. Each
+/// channel also receives the versions of every more stable channel.
+#[derive(
+	Serialize,
+	Deserialize,
+	Debug,
+	Hash,
+	PartialEq,
+	Eq,
+	PartialOrd,
+	Ord,
+	Clone,
+	Copy,
+	ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+	Stable,
+	Beta,
+	Alpha,
+	Snapshot
+}
+
+impl Channel {
+	/// The channel of a version, from the letters of the first identifier of
+	/// its pre-release. Versions without one are stable, and unknown
+	/// identifiers such as `SNAPSHOT`, `dev` or `preview` are snapshots.
+	pub fn of(version: &Version) -> Self {
+		if version.pre.is_empty() {
+			return Self::Stable;
+		}
+
+		// Only the letters are compared, as some versions were released like
+		// 1.0.0-alpha7 rather than 1.0.0-alpha.7
+		let identifier = version
+			.pre
+			.split('.')
+			.next()
+			.unwrap_or_default()
+			.chars()
+			.take_while(char::is_ascii_alphabetic)
+			.collect::<String>()
+			.to_ascii_lowercase();
+		match identifier.as_str() {
+			"beta" | "rc" | "pre" => Self::Beta,
+			"alpha" => Self::Alpha,
+			_ => Self::Snapshot
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn channel(version: &str) -> Channel {
+		Channel::of(&Version::parse(version).unwrap())
+	}
+
+	#[test]
+	fn channel_of_release_is_stable() {
+		assert_eq!(channel("1.0.0"), Channel::Stable);
+	}
+
+	#[test]
+	fn channel_of_pre_release_compares_letters() {
+		assert_eq!(channel("1.0.0-beta.1"), Channel::Beta);
+		assert_eq!(channel("1.0.0-RC.2"), Channel::Beta);
+		assert_eq!(channel("1.0.0-pre1"), Channel::Beta);
+		assert_eq!(channel("1.0.0-alpha.50"), Channel::Alpha);
+		assert_eq!(channel("1.0.0-alpha7"), Channel::Alpha);
+	}
+
+	#[test]
+	fn channel_of_unknown_identifier_is_snapshot() {
+		assert_eq!(channel("1.0.0-SNAPSHOT"), Channel::Snapshot);
+		assert_eq!(channel("1.0.0-preview.1"), Channel::Snapshot);
+		assert_eq!(channel("1.0.0-release"), Channel::Snapshot);
+		assert_eq!(channel("1.0.0-rcx"), Channel::Snapshot);
+	}
+}
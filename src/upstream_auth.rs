@@ -0,0 +1,101 @@
+use std::{env, fs, io, path::PathBuf, str::FromStr};
+
+use reqwest::RequestBuilder;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UpstreamAuthError {
+	#[error("The environment variable {0} holding upstream credentials is not set")]
+	MissingVariable(String),
+	#[error("Unable to read the upstream credentials file {0}: {1}")]
+	Read(PathBuf, io::Error),
+	#[error(
+		"The upstream credentials for {0} are not `basic USER:PASSWORD` or `bearer \
+		 TOKEN`"
+	)]
+	InvalidCredentials(String)
+}
+
+/// The credentials sent to a maven repository when resolving artifacts
+#[derive(Clone, PartialEq)]
+pub enum UpstreamAuth {
+	Basic { username: String, password: String },
+	Bearer(String)
+}
+
+impl UpstreamAuth {
+	pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+		match self {
+			Self::Basic { username, password } =>
+				request.basic_auth(username, Some(password)),
+			Self::Bearer(token) => request.bearer_auth(token)
+		}
+	}
+
+	fn parse(value: &str) -> Option<Self> {
+		let (scheme, credentials) = value.trim().split_once(' ')?;
+
+		match scheme.to_ascii_lowercase().as_str() {
+			"basic" => {
+				let (username, password) = credentials.split_once(':')?;
+				Some(Self::Basic {
+					username: username.to_string(),
+					password: password.to_string()
+				})
+			}
+			"bearer" => Some(Self::Bearer(credentials.to_string())),
+			_ => None
+		}
+	}
+}
+
+/// Where the credentials of a repository are read from, written as `env:VAR` or
+/// `file:PATH`. Either way, the value is `basic USER:PASSWORD` or `bearer
+/// TOKEN`, so the credentials themselves never have to be passed as flags.
+#[derive(Clone)]
+pub enum CredentialSource {
+	Env(String),
+	File(PathBuf)
+}
+
+impl CredentialSource {
+	pub fn read(&self) -> Result<UpstreamAuth, UpstreamAuthError> {
+		let (value, source) = match self {
+			Self::Env(name) => (
+				env::var(name)
+					.map_err(|_| UpstreamAuthError::MissingVariable(name.clone()))?,
+				name.clone()
+			),
+			Self::File(path) => (
+				fs::read_to_string(path)
+					.map_err(|e| UpstreamAuthError::Read(path.clone(), e))?,
+				path.display().to_string()
+			)
+		};
+
+		UpstreamAuth::parse(&value).ok_or(UpstreamAuthError::InvalidCredentials(source))
+	}
+}
+
+impl FromStr for CredentialSource {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.split_once(':') {
+			Some(("env", name)) => Ok(Self::Env(name.to_string())),
+			Some(("file", path)) => Ok(Self::File(path.into())),
+			_ => Err(format!("expected env:VAR or file:PATH, got {value}"))
+		}
+	}
+}
+
+/// Parses the credential source of a single repository, written as
+/// `REPOSITORY=SOURCE`, where a repository of `*` applies to every repository
+/// without its own credentials
+pub fn parse_repository_auth(value: &str) -> Result<(String, CredentialSource), String> {
+	let (repository, source) = value
+		.split_once('=')
+		.ok_or_else(|| format!("expected REPOSITORY=SOURCE, got {value}"))?;
+
+	Ok((repository.to_string(), source.parse()?))
+}